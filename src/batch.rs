@@ -0,0 +1,615 @@
+use anyhow::{anyhow, Result};
+use convert_case::{Case, Casing};
+use log::{error, info, warn};
+use path_clean::PathClean;
+use pathdiff::diff_paths;
+use rayon::prelude::*;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::feed::{page_date, summary};
+use crate::frontmatter::FrontMatter;
+use crate::links::{convert_wikilinks, extract_links, LinkKind};
+use crate::{
+    build_feed, build_search_index, to_html_and_save, write_sitemap, FeedEntry, ProgramOptions,
+    SearchEntry, VimWikiOptions, LOCAL_CONFIG_FILE,
+};
+
+/// All options of a batch conversion of a whole directory tree.
+///
+/// Every file with the wiki `extension` below `input_dir` is converted, the directory structure is
+/// mirrored below `output_dir`.
+#[derive(Debug)]
+pub struct BatchOptions {
+    extension: String,
+    template_file: PathBuf,
+    root_path: PathBuf,
+    output_dir: PathBuf,
+    input_dir: PathBuf,
+    css_file: PathBuf,
+    force: bool,
+}
+
+/// Returns the path from a page `depth` directories below the root to the `root_path`.
+fn nested_root_path(root_path: &Path, depth: usize) -> PathBuf {
+    if depth == 0 || root_path.is_absolute() {
+        return root_path.to_path_buf();
+    }
+    let root = root_path.to_str().unwrap_or("");
+    PathBuf::from(format!(
+        "{}{}",
+        "../".repeat(depth),
+        root.trim_start_matches("./")
+    ))
+}
+
+/// Name of the manifest in `output_dir` with the content hash of every generated page.
+const CACHE_FILE: &str = ".vimwiki-md2html.cache";
+
+/// Returns a hash of everything the html of `page` is generated from: its source, its template,
+/// the stylesheet, all options and the sorted `input_files` of the wiki, as links depend on which
+/// pages exist.
+///
+/// The hash is only compared with the one of a previous run, a different hash merely regenerates
+/// the page.
+fn content_hash(
+    page: &VimWikiOptions,
+    program_options: &ProgramOptions,
+    input_files: &[&Path],
+) -> Result<u64> {
+    let markdown = fs::read_to_string(&page.input_file)?;
+    let (front_matter, _) = FrontMatter::split(&markdown)?;
+    let mut hasher = DefaultHasher::new();
+    env!("CARGO_PKG_VERSION").hash(&mut hasher);
+    markdown.hash(&mut hasher);
+    page.template(program_options, &front_matter)
+        .hash(&mut hasher);
+    fs::read_to_string(page.output_dir.join(page.css_path()))
+        .unwrap_or_default()
+        .hash(&mut hasher);
+    format!("{:?}", page).hash(&mut hasher);
+    format!("{:?}", program_options).hash(&mut hasher);
+    input_files.hash(&mut hasher);
+    Ok(hasher.finish())
+}
+
+/// Reads the manifest `cache_file`, mapping the output paths relative to `output_dir` to the
+/// content hash of their last conversion. A missing or broken manifest is empty.
+fn read_cache(cache_file: &Path) -> HashMap<PathBuf, u64> {
+    fs::read_to_string(cache_file)
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| {
+            let (hash, path) = line.split_once(' ')?;
+            Some((PathBuf::from(path), u64::from_str_radix(hash, 16).ok()?))
+        })
+        .collect()
+}
+
+/// Collects all files with the extension `ext` below `dir` recursively.
+fn find_files(dir: &Path, ext: &str, files: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_files(&path, ext, files)?;
+        } else if path.extension().and_then(|e| e.to_str()) == Some(ext) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+impl BatchOptions {
+    pub fn new(
+        extension: &str,
+        template_file: &Path,
+        root_path: &Path,
+        output_dir: &Path,
+        input_dir: &Path,
+        css_file: &Path,
+        force: bool,
+    ) -> Self {
+        Self {
+            extension: extension.to_string(),
+            template_file: template_file.to_path_buf(),
+            root_path: root_path.to_path_buf(),
+            output_dir: output_dir.to_path_buf(),
+            input_dir: input_dir.to_path_buf(),
+            css_file: css_file.to_path_buf(),
+            force,
+        }
+    }
+
+    /// Returns the sorted paths of all pages below `input_dir`.
+    pub fn input_files(&self) -> Result<Vec<PathBuf>> {
+        let mut files = vec![];
+        find_files(&self.input_dir, &self.extension, &mut files)?;
+        files.sort();
+        Ok(files)
+    }
+
+    /// Returns the `VimWikiOptions` of every page below `input_dir`. The output directory of each
+    /// page mirrors its directory below `input_dir` and is created if missing.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the input directory cannot be read or an output directory cannot be
+    /// created.
+    pub fn pages(&self, program_options: &ProgramOptions) -> Result<Vec<VimWikiOptions>> {
+        self.input_files()?
+            .iter()
+            .map(|input_file| {
+                let mut page = VimWikiOptions::new(
+                    &self.extension,
+                    &self.template_file,
+                    &self.root_path,
+                    &self.output_dir,
+                    input_file,
+                    &self.css_file,
+                );
+                let output_file = page.nested_output_filepath(program_options, &self.input_dir)?;
+                let depth = page.relative_dir(&self.input_dir).components().count();
+                page.root_path = nested_root_path(&self.root_path, depth);
+                if let Some(output_dir) = output_file.parent() {
+                    page.output_dir = output_dir.to_path_buf();
+                }
+                Ok(page)
+            })
+            .collect()
+    }
+
+    /// Converts all pages in parallel. Unless `force` is set, pages are skipped if their html
+    /// exists and their content hash matches the one recorded in the manifest of `output_dir` by
+    /// the previous run. Returns the number of converted pages.
+    ///
+    /// All pages are converted with the same `program_options`, the configuration of the wiki
+    /// root. Configuration files in subdirectories are ignored with a warning.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the input directory cannot be read or if any page could not be
+    /// converted. All other pages are converted nonetheless.
+    pub fn convert(&self, program_options: &ProgramOptions) -> Result<usize> {
+        for config_file in self.nested_config_files()? {
+            warn!(
+                "Ignoring {}, only the configuration of the wiki root applies to a directory",
+                config_file.to_str().unwrap_or("")
+            );
+        }
+        let mut pages = self.pages(program_options)?;
+        // the backlinks need all pages, so they are collected before any page is converted
+        let mut backlinks = self.backlinks(&pages, program_options);
+        for page in pages.iter_mut() {
+            page.backlinks = backlinks.remove(&page.input_file).unwrap_or_default();
+        }
+        let cache_file = self.output_dir.join(CACHE_FILE);
+        let cache = if self.force {
+            HashMap::new()
+        } else {
+            read_cache(&cache_file)
+        };
+        let input_files: Vec<&Path> = pages.iter().map(|page| page.input_file.as_path()).collect();
+        let results: Vec<(&VimWikiOptions, Result<(bool, u64)>)> = pages
+            .par_iter()
+            .map(|page| {
+                let result = self.convert_page(page, program_options, &input_files, &cache);
+                (page, result)
+            })
+            .collect();
+
+        let mut converted = 0;
+        let mut failed = 0;
+        let mut manifest = String::new();
+        for (page, result) in results {
+            match result {
+                Ok((is_converted, hash)) => {
+                    if is_converted {
+                        converted += 1;
+                    }
+                    let path = self.relative_output_path(page, program_options);
+                    manifest.push_str(&format!("{:016x} {}\n", hash, path.to_str().unwrap_or("")));
+                }
+                Err(err) => {
+                    error!(
+                        "Could not convert {}: {}",
+                        page.input_file.to_str().unwrap_or(""),
+                        err
+                    );
+                    failed += 1;
+                }
+            }
+        }
+        if fs::create_dir_all(&self.output_dir)
+            .and_then(|_| fs::write(&cache_file, manifest))
+            .is_err()
+        {
+            error!("Could not write {}", cache_file.to_str().unwrap_or(""));
+        }
+        if failed > 0 {
+            Err(anyhow!("{} pages could not be converted", failed))
+        } else {
+            Ok(converted)
+        }
+    }
+
+    /// Returns the per-wiki configuration files in the subdirectories of `input_dir`.
+    fn nested_config_files(&self) -> Result<Vec<PathBuf>> {
+        let mut files = vec![];
+        find_files(&self.input_dir, "toml", &mut files)?;
+        files.retain(|file| {
+            file.file_name() == Some(LOCAL_CONFIG_FILE.as_ref())
+                && file.parent() != Some(self.input_dir.as_path())
+        });
+        files.sort();
+        Ok(files)
+    }
+
+    /// Converts a single `page` unless its html exists and its content hash is the one in
+    /// `cache`. Returns whether the page was converted and its content hash.
+    fn convert_page(
+        &self,
+        page: &VimWikiOptions,
+        program_options: &ProgramOptions,
+        input_files: &[&Path],
+        cache: &HashMap<PathBuf, u64>,
+    ) -> Result<(bool, u64)> {
+        let hash = content_hash(page, program_options, input_files)?;
+        let output_file = PathBuf::from(page.output_filepath(program_options));
+        let path = self.relative_output_path(page, program_options);
+        if cache.get(&path) == Some(&hash) && output_file.is_file() {
+            return Ok((false, hash));
+        }
+        fs::create_dir_all(&page.output_dir)?;
+        to_html_and_save(page, program_options)?;
+        info!("Generated {}", output_file.to_str().unwrap_or(""));
+        Ok((true, hash))
+    }
+
+    /// Returns the html list of backlinks for every page that is linked by other pages, keyed by
+    /// the input file of the linked page.
+    fn backlinks(
+        &self,
+        pages: &[VimWikiOptions],
+        program_options: &ProgramOptions,
+    ) -> HashMap<PathBuf, String> {
+        let input_files: HashMap<PathBuf, &VimWikiOptions> = pages
+            .iter()
+            .map(|page| (page.input_file.clean(), page))
+            .collect();
+        let mut linked_from: HashMap<PathBuf, Vec<&VimWikiOptions>> = HashMap::new();
+        for page in pages {
+            let markdown = match fs::read_to_string(&page.input_file) {
+                Ok(markdown) => convert_wikilinks(&markdown),
+                Err(_) => continue,
+            };
+            let input_dir = page.input_file.parent().unwrap_or_else(|| Path::new(""));
+            for link in extract_links(&markdown) {
+                if link.kind != LinkKind::Vimwiki {
+                    continue;
+                }
+                let path = link.uri.split(['#', '?']).next().unwrap_or("");
+                let target = input_dir.join(path).with_extension(&self.extension).clean();
+                if target == page.input_file.clean() {
+                    continue;
+                }
+                let sources = linked_from.entry(target).or_default();
+                if !sources.iter().any(|source| std::ptr::eq(*source, page)) {
+                    sources.push(page);
+                }
+            }
+        }
+
+        linked_from
+            .into_iter()
+            .filter_map(|(target, sources)| {
+                let target_page = input_files.get(&target)?;
+                let items: String = sources
+                    .iter()
+                    .map(|source| {
+                        let source_output = PathBuf::from(source.output_filepath(program_options));
+                        let href = diff_paths(&source_output, &target_page.output_dir)
+                            .unwrap_or(source_output);
+                        format!(
+                            "<li><a href=\"{}\">{}</a></li>",
+                            href.to_str().unwrap_or("").replace(' ', "%20"),
+                            crate::escape_html(&source.stem().to_case(Case::Title))
+                        )
+                    })
+                    .collect();
+                Some((
+                    target_page.input_file.clone(),
+                    format!(
+                        "<h2 class=\"backlinks\">Linked from</h2>\n<ul class=\"backlinks\">{}</ul>",
+                        items
+                    ),
+                ))
+            })
+            .collect()
+    }
+
+    /// Returns the path of the output of `page` relative to `output_dir`.
+    fn relative_output_path(
+        &self,
+        page: &VimWikiOptions,
+        program_options: &ProgramOptions,
+    ) -> PathBuf {
+        let output_file = PathBuf::from(page.output_filepath(program_options));
+        output_file
+            .strip_prefix(&self.output_dir)
+            .unwrap_or(&output_file)
+            .to_path_buf()
+    }
+
+    /// Writes an `atom.xml` feed of all dated pages to the root of `output_dir`. The date of a page
+    /// is taken from the `date` of its front matter or else from its filename. Returns the path of
+    /// the feed.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if a page cannot be read or converted or the feed cannot be written.
+    pub fn save_feed(&self, program_options: &ProgramOptions) -> Result<PathBuf> {
+        let mut entries = vec![];
+        for page in self.pages(program_options)? {
+            let markdown = fs::read_to_string(&page.input_file)?;
+            let (front_matter, _, body_html) = page.get_body_html(&markdown, program_options)?;
+            let date = match page_date(&front_matter.date, &page.stem()) {
+                Some(date) => date,
+                None => continue,
+            };
+            let path = self.relative_output_path(&page, program_options);
+            entries.push(FeedEntry {
+                title: page.title(&body_html, program_options),
+                link: format!(
+                    "{}/{}",
+                    program_options.base_url.trim_end_matches('/'),
+                    path.to_str().unwrap_or("").replace(' ', "%20")
+                ),
+                date,
+                summary: summary(&body_html),
+            });
+        }
+        let feed_file = self.output_dir.join("atom.xml");
+        fs::write(&feed_file, build_feed(&entries, &program_options.base_url))?;
+        Ok(feed_file)
+    }
+
+    /// Writes a `search-index.json` with the title, url and plain text of every page to the root
+    /// of `output_dir`. The urls are relative to `output_dir`, or prefixed with the configured
+    /// `base_url` if there is one. Returns the path of the search index.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if a page cannot be read or converted or the index cannot be written.
+    pub fn save_search_index(&self, program_options: &ProgramOptions) -> Result<PathBuf> {
+        let mut entries = vec![];
+        for page in self.pages(program_options)? {
+            let markdown = fs::read_to_string(&page.input_file)?;
+            let (_, _, body_html) = page.get_body_html(&markdown, program_options)?;
+            let path = self.relative_output_path(&page, program_options);
+            let path = path.to_str().unwrap_or("").replace(' ', "%20");
+            let url = if program_options.base_url.is_empty() {
+                path
+            } else {
+                format!(
+                    "{}/{}",
+                    program_options.base_url.trim_end_matches('/'),
+                    path
+                )
+            };
+            entries.push(SearchEntry::new(
+                &page.title(&body_html, program_options),
+                &url,
+                &body_html,
+            ));
+        }
+        let index_file = self.output_dir.join("search-index.json");
+        fs::write(&index_file, build_search_index(&entries))?;
+        Ok(index_file)
+    }
+
+    /// Writes a `sitemap.xml` listing all pages to the root of `output_dir`, the urls are prefixed
+    /// with the configured `base_url`. Returns the path of the sitemap.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the input directory cannot be read or the sitemap cannot be written.
+    pub fn save_sitemap(&self, program_options: &ProgramOptions) -> Result<PathBuf> {
+        let mut entries: Vec<(PathBuf, SystemTime)> = vec![];
+        for page in self.pages(program_options)? {
+            let path = self.relative_output_path(&page, program_options);
+            let modified = fs::metadata(&page.input_file)?.modified()?;
+            entries.push((path, modified));
+        }
+        let sitemap_file = self.output_dir.join("sitemap.xml");
+        fs::write(
+            &sitemap_file,
+            write_sitemap(&entries, &program_options.base_url),
+        )?;
+        Ok(sitemap_file)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn root_path_nested() {
+        let root = Path::new("./");
+        assert_eq!(Path::new("./"), nested_root_path(root, 0));
+        assert_eq!(Path::new("../"), nested_root_path(root, 1));
+        assert_eq!(Path::new("../../"), nested_root_path(root, 2));
+        assert_eq!(Path::new("../../"), nested_root_path(Path::new("../"), 1));
+        assert_eq!(
+            Path::new("/wiki/"),
+            nested_root_path(Path::new("/wiki/"), 2)
+        );
+    }
+
+    #[test]
+    fn nested_config_ignored() {
+        crate::test_log::init_logger();
+        let dir = std::env::temp_dir().join("vimwiki-markdown-rs-tests/nested_config");
+        let input_dir = dir.join("wiki");
+        let output_dir = dir.join("html");
+        fs::remove_dir_all(&dir).unwrap_or(());
+        fs::create_dir_all(input_dir.join("sub")).unwrap();
+        fs::write(input_dir.join(LOCAL_CONFIG_FILE), "").unwrap();
+        fs::write(
+            input_dir.join("sub").join(LOCAL_CONFIG_FILE),
+            "output_extension = \"htm\"\n",
+        )
+        .unwrap();
+        fs::write(input_dir.join("sub").join("page.wiki"), "# Page\n").unwrap();
+        let batch = BatchOptions::new(
+            "wiki",
+            Path::new(""),
+            Path::new("./"),
+            &output_dir,
+            &input_dir,
+            Path::new(""),
+            true,
+        );
+        assert_eq!(1, batch.convert(&ProgramOptions::default()).unwrap());
+        assert!(output_dir.join("sub/page.html").is_file());
+        assert_eq!(
+            1,
+            crate::test_log::warnings_containing("nested_config/wiki/sub/.vimwiki-markdown.toml")
+        );
+    }
+
+    #[test]
+    fn backlinks() {
+        let dir = std::env::temp_dir().join("vimwiki-markdown-rs-tests/backlinks");
+        let input_dir = dir.join("wiki");
+        let output_dir = dir.join("html");
+        fs::create_dir_all(&input_dir).unwrap();
+        fs::write(input_dir.join("page_a.wiki"), "See [B](page_b)\n").unwrap();
+        fs::write(input_dir.join("page_b.wiki"), "# B\n").unwrap();
+        let batch = BatchOptions::new(
+            "wiki",
+            Path::new(""),
+            Path::new("./"),
+            &output_dir,
+            &input_dir,
+            Path::new(""),
+            true,
+        );
+        let program_options = ProgramOptions::default();
+        assert_eq!(2, batch.convert(&program_options).unwrap());
+        let html_b = fs::read_to_string(output_dir.join("page_b.html")).unwrap();
+        assert!(html_b.contains("<li><a href=\"page_a.html\">Page A</a></li>"));
+        let html_a = fs::read_to_string(output_dir.join("page_a.html")).unwrap();
+        assert!(!html_a.contains("class=\"backlinks\""));
+    }
+
+    #[test]
+    fn parallel_same_as_sequential() {
+        let dir = std::env::temp_dir().join("vimwiki-markdown-rs-tests/parallel");
+        let input_dir = dir.join("wiki");
+        fs::create_dir_all(input_dir.join("sub")).unwrap();
+        for i in 0..8 {
+            fs::write(
+                input_dir.join(format!("page_{}.wiki", i)),
+                format!(
+                    "# Page {}\n\nSee [next](page_{}) and [sub](sub/index)\n",
+                    i,
+                    (i + 1) % 8
+                ),
+            )
+            .unwrap();
+        }
+        fs::write(
+            input_dir.join("sub/index.wiki"),
+            "Back to [start](../page_0)\n",
+        )
+        .unwrap();
+        let batch = |output_dir: &Path| {
+            BatchOptions::new(
+                "wiki",
+                Path::new(""),
+                Path::new("./"),
+                output_dir,
+                &input_dir,
+                Path::new(""),
+                true,
+            )
+        };
+        let program_options = ProgramOptions::default();
+
+        let parallel = batch(&dir.join("parallel"));
+        assert_eq!(9, parallel.convert(&program_options).unwrap());
+
+        let sequential = batch(&dir.join("sequential"));
+        let mut pages = sequential.pages(&program_options).unwrap();
+        let mut backlinks = sequential.backlinks(&pages, &program_options);
+        for page in pages.iter_mut() {
+            page.backlinks = backlinks.remove(&page.input_file).unwrap_or_default();
+            assert!(
+                sequential
+                    .convert_page(page, &program_options, &[], &HashMap::new())
+                    .unwrap()
+                    .0
+            );
+        }
+
+        for page in pages {
+            let path = sequential.relative_output_path(&page, &program_options);
+            let expected = fs::read_to_string(dir.join("sequential").join(&path)).unwrap();
+            let actual = fs::read_to_string(dir.join("parallel").join(&path)).unwrap();
+            assert_eq!(expected, actual, "{:?}", path);
+        }
+    }
+
+    #[test]
+    fn content_hash_cache() {
+        let dir = std::env::temp_dir().join("vimwiki-markdown-rs-tests/content_hash");
+        let input_dir = dir.join("wiki");
+        let output_dir = dir.join("html");
+        fs::remove_dir_all(&dir).unwrap_or(());
+        fs::create_dir_all(&input_dir).unwrap();
+        fs::write(input_dir.join("kept.wiki"), "# Kept\n").unwrap();
+        fs::write(input_dir.join("edited.wiki"), "# Edited\n").unwrap();
+        let batch = |force| {
+            BatchOptions::new(
+                "wiki",
+                Path::new(""),
+                Path::new("./"),
+                &output_dir,
+                &input_dir,
+                Path::new(""),
+                force,
+            )
+        };
+        let program_options = ProgramOptions::default();
+        assert_eq!(2, batch(false).convert(&program_options).unwrap());
+        assert_eq!(0, batch(false).convert(&program_options).unwrap());
+
+        fs::write(input_dir.join("edited.wiki"), "# Edited again\n").unwrap();
+        assert_eq!(1, batch(false).convert(&program_options).unwrap());
+        let html = fs::read_to_string(output_dir.join("edited.html")).unwrap();
+        assert!(html.contains("Edited again"));
+
+        // other options change the hash of every page
+        let program_options = ProgramOptions {
+            words_per_minute: 100,
+            ..ProgramOptions::default()
+        };
+        assert_eq!(2, batch(false).convert(&program_options).unwrap());
+        assert_eq!(2, batch(true).convert(&program_options).unwrap());
+
+        // a new page changes the links to it in the existing pages
+        fs::write(input_dir.join("new.wiki"), "# New\n").unwrap();
+        assert_eq!(3, batch(false).convert(&program_options).unwrap());
+        assert_eq!(0, batch(false).convert(&program_options).unwrap());
+
+        // so does the stylesheet linked by the pages
+        fs::write(output_dir.join("style.css"), "body { color: red; }\n").unwrap();
+        assert_eq!(3, batch(false).convert(&program_options).unwrap());
+        assert_eq!(0, batch(false).convert(&program_options).unwrap());
+    }
+}
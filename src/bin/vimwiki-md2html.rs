@@ -1,31 +1,108 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use chrono::Local;
 use env_logger::Env;
-use log::info;
-use std::path::PathBuf;
+use log::{error, info, LevelFilter};
+use notify::{RecursiveMode, Watcher};
+use std::env;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::mpsc::{channel, Receiver};
+use std::time::Duration;
 use structopt::StructOpt;
 
-use vimwiki_markdown_rs::VimWikiOptions;
+use vimwiki_markdown_rs::{BatchOptions, ProgramOptions, VimWikiOptions};
+
+/// Time without further changes before a watched file is converted again
+const DEBOUNCE_DELAY: Duration = Duration::from_millis(200);
 
 #[derive(StructOpt, Debug)]
-#[structopt(name = "vimwiki-md2html")]
+#[structopt(
+    name = "vimwiki-md2html",
+    after_help = "Logs at the info level by default. `RUST_LOG` overrides the default, \
+                  `--verbose` and `--quiet` override both."
+)]
 struct Opt {
+    /// Log debug messages, repeat to log trace messages as well
     #[structopt(short, long, parse(from_occurrences))]
     verbose: u8,
 
+    /// Only log warnings and errors
+    #[structopt(short, long, conflicts_with = "verbose")]
+    quiet: bool,
+
     #[structopt(short = "e", long = "ext", default_value = "wiki")]
     extension: String,
 
+    /// Template file or name of a template in the template directory, `-` reads the template from
+    /// stdin
     #[structopt(short = "t", long = "template", default_value = "default")]
     template_file: PathBuf,
 
     #[structopt(long = "root", default_value = "./")]
     root_path: PathBuf,
 
-    #[structopt(short = "o", long = "output")]
-    output_dir: PathBuf,
+    /// Stylesheet linked by the template, relative to the root
+    #[structopt(long = "css", default_value = "style.css")]
+    css_file: PathBuf,
+
+    /// Configuration file to use instead of the one in the default location
+    #[structopt(short = "c", long = "config")]
+    config: Option<PathBuf>,
+
+    /// Output directory, `-` writes the html to stdout
+    #[structopt(
+        short = "o",
+        long = "output",
+        required_unless_one = &["list-themes", "check"]
+    )]
+    output_dir: Option<PathBuf>,
+
+    /// Regenerate the html file whenever the input file or the template changes
+    #[structopt(short = "w", long = "watch")]
+    watch: bool,
+
+    /// Open the generated html file in the default browser
+    #[structopt(long = "open")]
+    open: bool,
+
+    /// Convert as usual, but only log the file `--open` would show instead of opening it
+    #[structopt(long = "dry-run")]
+    dry_run: bool,
+
+    /// Convert all pages of an input directory, even if they are unchanged since the last run
+    #[structopt(short = "f", long = "force")]
+    force: bool,
+
+    /// Write a `sitemap.xml` of all pages when converting a directory
+    #[structopt(long = "sitemap")]
+    sitemap: bool,
+
+    /// Write an `atom.xml` feed of all dated pages when converting a directory
+    #[structopt(long = "feed")]
+    feed: bool,
+
+    /// Write a `search-index.json` of all pages when converting a directory
+    #[structopt(long = "search-index")]
+    search_index: bool,
 
-    #[structopt(name = "FILE")]
-    input_file: PathBuf,
+    /// Only generate the body html, without any template
+    #[structopt(long = "fragment")]
+    fragment: bool,
+
+    /// Report broken links, undefined or cyclic variables and unknown commands of the input file or
+    /// of all pages of an input directory without writing any html, fail if there are any
+    #[structopt(long = "check")]
+    check: bool,
+
+    /// Print the names accepted as `highlight_theme` by the configured backend and exit
+    #[structopt(long = "list-themes")]
+    list_themes: bool,
+
+    /// Input file, `-` reads the markdown from stdin. A directory converts all pages below it
+    #[structopt(name = "FILE", required_unless = "list-themes")]
+    input_file: Option<PathBuf>,
 }
 
 impl From<Opt> for VimWikiOptions {
@@ -34,24 +111,418 @@ impl From<Opt> for VimWikiOptions {
             &opt.extension,
             &opt.template_file,
             &opt.root_path,
-            &opt.output_dir,
-            &opt.input_file,
+            &opt.output_dir.unwrap_or_default(),
+            &opt.input_file.unwrap_or_default(),
+            &opt.css_file,
+        )
+    }
+}
+
+/// Blocks until no message was received for `delay` and returns the number of drained messages.
+///
+/// Editors often write a file several times in a row when saving, this collapses them into a
+/// single regeneration.
+fn debounce<T>(rx: &Receiver<T>, delay: Duration) -> usize {
+    let mut drained = 0;
+    while rx.recv_timeout(delay).is_ok() {
+        drained += 1;
+    }
+    drained
+}
+
+/// Returns the absolute path of `path`, which might not exist yet, if its directory exists.
+fn absolute_path(path: &Path) -> Option<PathBuf> {
+    if let Ok(path) = path.canonicalize() {
+        return Some(path);
+    }
+    let dir = match path.parent() {
+        Some(dir) if dir != Path::new("") => dir,
+        _ => Path::new("."),
+    };
+    Some(dir.canonicalize().ok()?.join(path.file_name()?))
+}
+
+/// Converts the input file every time one of its source files changes, until interrupted. The
+/// source files are determined again after every conversion, as the front matter may select
+/// another template.
+fn watch(wiki_options: &VimWikiOptions, program_options: &ProgramOptions) -> Result<()> {
+    let (tx, rx) = channel();
+    let mut watcher = notify::recommended_watcher(tx)?;
+    let mut watched_dirs: Vec<PathBuf> = vec![];
+    let mut watch_sources = || -> Result<Vec<PathBuf>> {
+        let watched: Vec<PathBuf> = wiki_options
+            .source_files(program_options)
+            .iter()
+            .filter_map(|path| absolute_path(path))
+            .collect();
+        // watch the directories, as editors might replace the files when saving
+        for path in &watched {
+            let dir = path.parent().unwrap_or(path).to_path_buf();
+            if !watched_dirs.contains(&dir) {
+                watcher.watch(&dir, RecursiveMode::NonRecursive)?;
+                watched_dirs.push(dir);
+            }
+        }
+        Ok(watched)
+    };
+    let mut watched = watch_sources()?;
+
+    info!("Watching for changes...");
+    loop {
+        let event = match rx.recv()? {
+            Ok(event) => event,
+            Err(err) => {
+                error!("Watching failed: {}", err);
+                continue;
+            }
+        };
+        let relevant = event
+            .paths
+            .iter()
+            .any(|path| absolute_path(path).is_some_and(|path| watched.contains(&path)));
+        if !relevant || event.kind.is_access() {
+            continue;
+        }
+        debounce(&rx, DEBOUNCE_DELAY);
+        match vimwiki_markdown_rs::to_html_and_save(wiki_options, program_options) {
+            Ok(()) => info!(
+                "Regenerated {} at {}",
+                wiki_options.output_filepath(program_options),
+                Local::now().format("%H:%M:%S")
+            ),
+            Err(err) => error!("Could not regenerate html file: {}", err),
+        }
+        watched = watch_sources()?;
+    }
+}
+
+/// Returns the log level set by the `--verbose` count and `--quiet`, `None` keeps the level of
+/// `RUST_LOG` or the default.
+fn log_level(verbose: u8, quiet: bool) -> Option<LevelFilter> {
+    match (verbose, quiet) {
+        (_, true) => Some(LevelFilter::Warn),
+        (0, false) => None,
+        (1, false) => Some(LevelFilter::Debug),
+        _ => Some(LevelFilter::Trace),
+    }
+}
+
+/// Returns the command opening `file` in the default browser of the platform.
+fn browser_command(file: &Path) -> Command {
+    let mut command = if cfg!(target_os = "macos") {
+        Command::new("open")
+    } else if cfg!(target_os = "windows") {
+        let mut command = Command::new("cmd");
+        command.args(["/C", "start", ""]);
+        command
+    } else {
+        Command::new("xdg-open")
+    };
+    command.arg(file);
+    command
+}
+
+/// Opens `file` with `launch`, e.g. spawning the `browser_command`. Nothing is launched if
+/// `dry_run` is set.
+fn open_in_browser<F>(file: &Path, dry_run: bool, launch: F) -> Result<()>
+where
+    F: FnOnce(&Path) -> io::Result<()>,
+{
+    if dry_run {
+        info!("Dry run, not opening {}", file.to_str().unwrap_or(""));
+        return Ok(());
+    }
+    info!("Opening {} in the browser", file.to_str().unwrap_or(""));
+    launch(file).map_err(|err| anyhow!("Could not open the browser: {}", err))
+}
+
+/// Writes the names accepted as `highlight_theme` to `out`, one per line.
+fn list_themes(program_options: &ProgramOptions, out: &mut impl Write) -> io::Result<()> {
+    for name in program_options.highlight_themes() {
+        writeln!(out, "{}", name)?;
+    }
+    Ok(())
+}
+
+/// Prints the problems of the input file, or of all pages if it is a directory, see
+/// `VimWikiOptions::check`.
+///
+/// # Errors
+///
+/// Will return `Err` if any problem is found or a page cannot be read.
+fn check(opt: &Opt, input_file: &Path) -> Result<()> {
+    if input_file == Path::new("-") {
+        return Err(anyhow!("--check cannot read from stdin"));
+    }
+    let program_options = program_options(opt, input_file)?;
+    // only the input files are needed, nothing is written
+    let input_files = if input_file.is_dir() {
+        BatchOptions::new(
+            &opt.extension,
+            &opt.template_file,
+            &opt.root_path,
+            Path::new(""),
+            input_file,
+            &opt.css_file,
+            false,
         )
+        .input_files()?
+    } else {
+        vec![input_file.to_path_buf()]
+    };
+    let pages: Vec<VimWikiOptions> = input_files
+        .iter()
+        .map(|input_file| {
+            VimWikiOptions::new(
+                &opt.extension,
+                &opt.template_file,
+                &opt.root_path,
+                Path::new(""),
+                input_file,
+                &opt.css_file,
+            )
+        })
+        .collect();
+    let mut problems = 0;
+    for page in &pages {
+        for problem in page.check(&program_options)? {
+            println!("{}", problem);
+            problems += 1;
+        }
     }
+    if problems > 0 {
+        return Err(anyhow!(
+            "Found {} problems in {} pages",
+            problems,
+            pages.len()
+        ));
+    }
+    info!("Checked {} pages, no problems found", pages.len());
+    Ok(())
+}
+
+/// Loads the configuration file given by `--config`, or the default configuration merged with the
+/// per-wiki configuration files found above `input_file`. Flags of `opt` override the configuration.
+fn program_options(opt: &Opt, input_file: &Path) -> Result<ProgramOptions> {
+    info!("Loading configuration file...");
+    let mut program_options = match &opt.config {
+        Some(config) => ProgramOptions::from_path(config)?,
+        None => ProgramOptions::load_for_input(input_file)?,
+    };
+    if opt.fragment {
+        program_options.set_fragment_only(true);
+    }
+    Ok(program_options)
 }
 
 fn main() -> Result<()> {
-    env_logger::from_env(Env::default().default_filter_or("INFO")).init();
+    let mut opt = Opt::from_args();
+    let mut logger = env_logger::from_env(Env::default().default_filter_or("INFO"));
+    if let Some(level) = log_level(opt.verbose, opt.quiet) {
+        logger.filter_level(level);
+    }
+    logger.init();
+
+    if opt.list_themes {
+        let program_options = program_options(&opt, &env::current_dir()?)?;
+        list_themes(&program_options, &mut io::stdout())?;
+        return Ok(());
+    }
+    // both are required unless the themes are listed
+    let input_file = opt.input_file.clone().unwrap_or_default();
+    if opt.check {
+        return check(&opt, &input_file);
+    }
+    let output_dir = opt.output_dir.clone().unwrap_or_default();
+
+    let read_stdin = input_file == Path::new("-");
+    let write_stdout = output_dir == Path::new("-");
+    let template_stdin = opt.template_file == Path::new("-");
+    if template_stdin && read_stdin {
+        return Err(anyhow!(
+            "The template and the input cannot both be read from stdin"
+        ));
+    }
+    if template_stdin && (opt.watch || input_file.is_dir()) {
+        return Err(anyhow!(
+            "A template from stdin cannot be used with --watch or to convert a directory"
+        ));
+    }
+    if opt.watch && (read_stdin || write_stdout) {
+        return Err(anyhow!("--watch cannot be combined with stdin or stdout"));
+    }
+    if opt.open && write_stdout {
+        return Err(anyhow!("--open cannot be combined with stdout"));
+    }
+
+    if input_file.is_dir() {
+        if opt.watch || opt.open || write_stdout {
+            return Err(anyhow!(
+                "--watch, --open and stdout cannot be used to convert a directory"
+            ));
+        }
+        let batch_options = BatchOptions::new(
+            &opt.extension,
+            &opt.template_file,
+            &opt.root_path,
+            &output_dir,
+            &input_file,
+            &opt.css_file,
+            opt.force,
+        );
+        let program_options = program_options(&opt, &input_file)?;
+        info!("Generating html files...");
+        let converted = batch_options.convert(&program_options)?;
+        info!("Converted {} pages", converted);
+        if opt.sitemap {
+            let sitemap_file = batch_options.save_sitemap(&program_options)?;
+            info!("Generated {}", sitemap_file.to_str().unwrap_or(""));
+        }
+        if opt.feed {
+            let feed_file = batch_options.save_feed(&program_options)?;
+            info!("Generated {}", feed_file.to_str().unwrap_or(""));
+        }
+        if opt.search_index {
+            let index_file = batch_options.save_search_index(&program_options)?;
+            info!("Generated {}", index_file.to_str().unwrap_or(""));
+        }
+        return Ok(());
+    }
+
+    // links are resolved relative to the current directory when streaming
+    let current_dir = env::current_dir()?;
+    if read_stdin {
+        opt.input_file = Some(current_dir.join("stdin"));
+    }
+    if write_stdout {
+        opt.output_dir = Some(current_dir);
+    }
 
-    info!("Parsing commandline arguments");
-    let opt = Opt::from_args();
+    let watch_mode = opt.watch;
+    let open = opt.open;
+    let dry_run = opt.dry_run;
+    let input_file = opt.input_file.clone().unwrap_or_default();
 
     // get user specific configurations
-    info!("Loading configuration file...");
-    let program_options = vimwiki_markdown_rs::ProgramOptions::new();
+    let program_options = program_options(&opt, &input_file)?;
+    let mut wiki_options: VimWikiOptions = opt.into();
+    if template_stdin {
+        let mut template = String::new();
+        io::stdin().read_to_string(&mut template)?;
+        wiki_options.set_template(template);
+    }
+
+    let launch = |file: &Path| browser_command(file).spawn().map(|_| ());
+    let output_file = PathBuf::from(wiki_options.output_filepath(&program_options));
 
     // run function
     info!("Generating html file...");
-    vimwiki_markdown_rs::to_html_and_save(&opt.into(), &program_options)?;
+    if watch_mode {
+        // keep watching even if the first conversion fails
+        if let Err(err) = vimwiki_markdown_rs::to_html_and_save(&wiki_options, &program_options) {
+            error!("Could not generate html file: {}", err);
+        } else if open {
+            open_in_browser(&output_file, dry_run, launch)?;
+        }
+        watch(&wiki_options, &program_options)?;
+    } else if read_stdin || write_stdout {
+        let html = if read_stdin {
+            let mut markdown = String::new();
+            io::stdin().read_to_string(&mut markdown)?;
+            vimwiki_markdown_rs::to_html_from_str(&wiki_options, &program_options, &markdown)?
+        } else {
+            vimwiki_markdown_rs::to_html(&wiki_options, &program_options)?
+        };
+        if write_stdout {
+            io::stdout().write_all(html.as_bytes())?;
+        } else {
+            fs::write(&output_file, html)?;
+        }
+    } else {
+        vimwiki_markdown_rs::to_html_and_save(&wiki_options, &program_options)?;
+    }
+    if open {
+        open_in_browser(&output_file, dry_run, launch)?;
+    }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn debounce_drains_bursts() {
+        let (tx, rx) = channel();
+        for i in 0..3 {
+            tx.send(i).unwrap();
+        }
+        assert_eq!(3, debounce(&rx, Duration::from_millis(10)));
+        assert_eq!(0, debounce(&rx, Duration::from_millis(10)));
+    }
+
+    #[test]
+    fn open_flag() {
+        let opt = Opt::from_iter(&["vimwiki-md2html", "--open", "-o", "html", "page.md"]);
+        assert!(opt.open);
+        let opt = Opt::from_iter(&["vimwiki-md2html", "-o", "html", "page.md"]);
+        assert!(!opt.open);
+        assert!(!opt.dry_run);
+        let opt = Opt::from_iter(&[
+            "vimwiki-md2html",
+            "--open",
+            "--dry-run",
+            "-o",
+            "html",
+            "page.md",
+        ]);
+        assert!(opt.open && opt.dry_run);
+    }
+
+    #[test]
+    fn open_in_browser_launches_file() {
+        let mut opened = None;
+        open_in_browser(Path::new("html/page.html"), false, |file| {
+            opened = Some(file.to_path_buf());
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(Some(PathBuf::from("html/page.html")), opened);
+
+        let failed = open_in_browser(Path::new("html/page.html"), false, |_| {
+            Err(io::Error::new(io::ErrorKind::NotFound, "no browser"))
+        });
+        assert!(failed.is_err());
+    }
+
+    #[test]
+    fn open_in_browser_dry_run() {
+        let mut launched = false;
+        open_in_browser(Path::new("html/page.html"), true, |_| {
+            launched = true;
+            Ok(())
+        })
+        .unwrap();
+        assert!(!launched);
+    }
+
+    #[test]
+    fn list_themes_flag() {
+        let opt = Opt::from_iter(&["vimwiki-md2html", "--list-themes"]);
+        assert!(opt.list_themes);
+        assert!(Opt::from_iter_safe(&["vimwiki-md2html", "-o", "html"]).is_err());
+
+        let mut out = vec![];
+        list_themes(&ProgramOptions::default(), &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.lines().any(|name| name == "default"));
+    }
+
+    #[test]
+    fn log_level_flags() {
+        assert_eq!(None, log_level(0, false));
+        assert_eq!(Some(LevelFilter::Debug), log_level(1, false));
+        assert_eq!(Some(LevelFilter::Trace), log_level(3, false));
+        assert_eq!(Some(LevelFilter::Warn), log_level(0, true));
+    }
+}
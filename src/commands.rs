@@ -1,17 +1,51 @@
+use anyhow::{anyhow, Result};
 use kuchiki::traits::*;
+use kuchiki::NodeRef;
 use lazy_static::lazy_static;
+use log::warn;
 use regex::{Captures, Regex};
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+
+use crate::dom::new_element;
+use crate::escape_html;
+
+/// Upper bound of substitution passes, guards against cyclic variable definitions
+const MAX_EXPANSION_DEPTH: usize = 32;
+
+/// Escaped sigil `\'{`, which is never interpreted as a variable or command
+const ESCAPED_SIGIL: &str = "\\'{";
+
+/// Stands in for `ESCAPED_SIGIL` (a private use character) until all commands are applied
+const ESCAPED_SIGIL_PLACEHOLDER: &str = "\u{E000}";
+
+/// Stand in for the quotes around commands (private use characters) while the markdown is parsed,
+/// so smart punctuation cannot turn them into typographic quotes
+const COMMAND_OPEN_PLACEHOLDER: &str = "\u{E001}";
+const COMMAND_CLOSE_PLACEHOLDER: &str = "\u{E002}";
+
+/// Variable definitions of a page, mapping names to values
+pub type Variables = HashMap<String, String>;
+
+/// Prefix of definitions which declare an abbreviation instead of a variable, e.g.
+/// `abbr:HTML{HyperText Markup Language}`
+const ABBREVIATION_PREFIX: &str = "abbr:";
+
+/// Elements whose text is never wrapped in an abbreviation
+const ABBREVIATION_EXCLUDED: [&str; 7] = ["a", "abbr", "code", "pre", "script", "style", "head"];
 
 struct VarStore {
-    map: HashMap<String, String>,
+    map: Variables,
 }
 
 lazy_static! {
-    static ref RE_DEF: Regex = Regex::new(r"<'''(?P<data>(.|\n)*)'''>").unwrap();
+    static ref RE_DEF: Regex = Regex::new(r"<'''(?P<data>(.|\n)*?)'''>").unwrap();
     static ref RE_DEF_SINGLE: Regex = Regex::new(r"(?P<key>\S*?)\{(?P<value>[^}]*?)\}").unwrap();
-    static ref RE_VAR: Regex =
-        Regex::new(r"'\{(?P<before>.*?)\$(?P<var>\S+?)(?P<after>(\s.*?\}|\}))'").unwrap();
+    static ref RE_VAR: Regex = Regex::new(
+        r"'\{(?P<before>.*?)\$(?P<var>[A-Za-z0-9_.\-]+)(:(?P<default>[^\s$}][^$}]*))?(?P<after>.*?\})'"
+    )
+    .unwrap();
+    static ref RE_VAR_REF: Regex = Regex::new(r"\$(?P<var>[A-Za-z0-9_.\-]+)").unwrap();
     static ref RE_CMD: Regex =
         Regex::new(r"'\{(?P<element>\S+)\s+(?P<type>\S+)\s+(?P<data>.*?)\}'").unwrap();
 }
@@ -24,85 +58,1029 @@ impl VarStore {
     }
 
     fn parse_variables(&mut self, input: &str) {
-        // parse defined commands
-        let mut caps_it = RE_DEF.captures_iter(&input);
-        let capture = caps_it.next();
-        match capture {
-            Some(c) => {
-                RE_DEF_SINGLE
-                    .captures_iter(&c["data"])
-                    .into_iter()
-                    .for_each(|e| {
-                        self.map.insert(e["key"].to_owned(), e["value"].to_owned());
-                    });
+        // parse defined commands of all blocks, later definitions override earlier ones
+        RE_DEF.captures_iter(input).for_each(|c| {
+            RE_DEF_SINGLE.captures_iter(&c["data"]).for_each(|e| {
+                self.map.insert(e["key"].to_owned(), e["value"].to_owned());
+            });
+        });
+    }
+
+    /// Returns the variables referenced by the value of `var`.
+    fn dependencies<'a>(&'a self, var: &str) -> Vec<&'a str> {
+        self.map.get(var).map_or(vec![], |value| {
+            RE_VAR_REF
+                .captures_iter(value)
+                .filter_map(|caps| {
+                    self.map
+                        .get_key_value(&caps["var"])
+                        .map(|(key, _)| key.as_str())
+                })
+                .collect()
+        })
+    }
+
+    /// Depth-first search for a cycle through `var`, `path` holds the variables being visited.
+    fn visit<'a>(
+        &'a self,
+        var: &'a str,
+        path: &mut Vec<&'a str>,
+        finished: &mut HashSet<&'a str>,
+    ) -> Option<Vec<&'a str>> {
+        if let Some(start) = path.iter().position(|visited| *visited == var) {
+            let mut cycle = path[start..].to_vec();
+            cycle.push(var);
+            return Some(cycle);
+        }
+        if finished.contains(var) {
+            return None;
+        }
+        path.push(var);
+        for dependency in self.dependencies(var) {
+            if let Some(cycle) = self.visit(dependency, path, finished) {
+                return Some(cycle);
             }
-            None => (),
         }
+        path.pop();
+        finished.insert(var);
+        None
+    }
+
+    /// Checks the definitions for variables which (indirectly) reference themselves.
+    fn check_cycles(&self) -> Result<()> {
+        let mut vars: Vec<&str> = self.map.keys().map(String::as_str).collect();
+        vars.sort_unstable();
+        let mut finished = HashSet::new();
+        for var in vars {
+            if let Some(cycle) = self.visit(var, &mut vec![], &mut finished) {
+                return Err(anyhow!(
+                    "Cyclic variable definition: {}",
+                    cycle.join(" -> ")
+                ));
+            }
+        }
+        Ok(())
     }
 
     fn clear_variables(&self, text: &str) -> String {
         RE_DEF.replace_all(&text, "").to_string()
     }
 
-    fn replace_variables(&self, text: &str) -> String {
-        // Checks whether variables were used and replaces them
-        // TODO: do this recursively, until all occurences are fixed
-        RE_VAR
+    /// Returns the value of the variable captured by `RE_VAR`, or its default (`$var:default`) if
+    /// the variable is not defined. The default directly follows the colon and ends before the
+    /// next variable, so `$prop: $value` is no default.
+    fn value<'a>(&'a self, caps: &'a Captures) -> Option<&'a str> {
+        self.map
+            .get(&caps["var"])
+            .map(String::as_str)
+            .or_else(|| caps.name("default").map(|default| default.as_str()))
+    }
+
+    fn replace_variables_once(&self, text: &str) -> Result<String> {
+        // Checks whether all used variables are defined
+        if let Some(caps) = RE_VAR
+            .captures_iter(text)
+            .find(|caps| self.value(caps).is_none())
+        {
+            return Err(anyhow!(
+                "Cannot find variable `{}` used in `{}`",
+                &caps["var"],
+                &caps[0]
+            ));
+        }
+
+        // replace the variables by their values
+        Ok(RE_VAR
             .replace_all(&text, |caps: &Captures| {
-                let val = match self.map.get(&caps["var"]) {
-                    Some(value) => value,
-                    None => panic!("Cannot find variable `{}`", &caps["var"]),
-                };
+                let val = self.value(caps).unwrap_or("");
                 // due to the nature of the regex, the last } will always be included at the end
                 let before = &caps["before"];
                 let after = &caps["after"][0..&caps["after"].len() - 1];
                 format!("'{{{}{}{}}}'", before, val, after)
             })
-            .to_string()
+            .to_string())
     }
 
-    /// Parses an input (content of markdown file) for commands and returns a cleaned text
-    pub fn parse(&mut self, input: &str) -> String {
-        self.parse_variables(input);
-        let cleaned = self.clear_variables(input);
-        self.replace_variables(&cleaned)
+    fn replace_variables(&self, text: &str) -> Result<String> {
+        // values may reference other variables, expand until nothing changes anymore
+        let mut expanded = text.to_owned();
+        for _ in 0..MAX_EXPANSION_DEPTH {
+            let next = self.replace_variables_once(&expanded)?;
+            if next == expanded {
+                break;
+            }
+            expanded = next;
+        }
+        if RE_VAR.is_match(&expanded) {
+            warn!("Variables could not be expanded, their definitions might be cyclic");
+            return Ok(text.to_owned());
+        }
+        Ok(expanded)
+    }
+
+    /// Parses an input (content of markdown file) for commands and returns a cleaned text. Code
+    /// spans and fenced code blocks are left as they are.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if a used variable is not defined or the definitions are cyclic.
+    pub fn parse(&mut self, input: &str) -> Result<String> {
+        let segments: Vec<(String, bool)> = split_code(input)
+            .into_iter()
+            .map(|(text, is_code)| {
+                if is_code {
+                    (text.to_owned(), true)
+                } else {
+                    (
+                        text.replace(ESCAPED_SIGIL, ESCAPED_SIGIL_PLACEHOLDER),
+                        false,
+                    )
+                }
+            })
+            .collect();
+        for (text, is_code) in &segments {
+            if !is_code {
+                self.parse_variables(text);
+            }
+        }
+        self.check_cycles()?;
+        segments
+            .iter()
+            .map(|(text, is_code)| {
+                if *is_code {
+                    Ok(text.to_owned())
+                } else {
+                    self.replace_variables(&self.clear_variables(text))
+                }
+            })
+            .collect()
+    }
+}
+
+/// Returns the opening fence of a fenced code block (three or more backticks or tildes) starting `line`.
+fn opening_fence(line: &str) -> Option<&str> {
+    let fence_line = line.trim_start_matches(' ');
+    if line.len() - fence_line.len() > 3 {
+        return None;
+    }
+    let fence_char = fence_line
+        .chars()
+        .next()
+        .filter(|c| *c == '`' || *c == '~')?;
+    let fence = &fence_line[..fence_line.len() - fence_line.trim_start_matches(fence_char).len()];
+    if fence.len() < 3 || (fence_char == '`' && fence_line[fence.len()..].contains('`')) {
+        return None;
+    }
+    Some(fence)
+}
+
+/// Returns whether `line` closes the code block opened by `fence`.
+fn is_closing_fence(line: &str, fence: &str) -> bool {
+    let fence_char = fence.chars().next().unwrap_or('`');
+    let fence_line = line.trim_start_matches(' ');
+    let rest = fence_line.trim_start_matches(fence_char);
+    line.len() - fence_line.len() <= 3
+        && fence_line.len() - rest.len() >= fence.len()
+        && rest.trim().is_empty()
+}
+
+/// Returns the length of the run of backticks at the start of `text`.
+fn backtick_run(text: &str) -> usize {
+    text.len() - text.trim_start_matches('`').len()
+}
+
+/// Splits the code spans of `text` from the text around them.
+fn split_code_spans<'a>(text: &'a str, segments: &mut Vec<(&'a str, bool)>) {
+    let mut last = 0;
+    let mut pos = 0;
+    while let Some(start) = text[pos..].find('`').map(|offset| pos + offset) {
+        let run = backtick_run(&text[start..]);
+        // the closing backticks have to be a run of the same length
+        let mut search = start + run;
+        let mut end = None;
+        while let Some(close) = text[search..].find('`').map(|offset| search + offset) {
+            let close_run = backtick_run(&text[close..]);
+            if close_run == run {
+                end = Some(close + run);
+                break;
+            }
+            search = close + close_run;
+        }
+        match end {
+            Some(end) => {
+                segments.push((&text[last..start], false));
+                segments.push((&text[start..end], true));
+                last = end;
+                pos = end;
+            }
+            None => pos = start + run,
+        }
+    }
+    segments.push((&text[last..], false));
+}
+
+/// Splits `markdown` into segments of code (fenced code blocks and code spans) and the text around
+/// them, each marked whether it is code.
+pub fn split_code(markdown: &str) -> Vec<(&str, bool)> {
+    let mut segments = vec![];
+    let mut fence = None;
+    let mut start = 0;
+    let mut pos = 0;
+    for line in markdown.split_inclusive('\n') {
+        match fence {
+            None => {
+                if let Some(opening) = opening_fence(line) {
+                    split_code_spans(&markdown[start..pos], &mut segments);
+                    fence = Some(opening);
+                    start = pos;
+                }
+            }
+            Some(opening) => {
+                if is_closing_fence(line, opening) {
+                    segments.push((&markdown[start..pos + line.len()], true));
+                    fence = None;
+                    start = pos + line.len();
+                }
+            }
+        }
+        pos += line.len();
     }
+    match fence {
+        // an unclosed code block lasts until the end of the document
+        Some(_) => segments.push((&markdown[start..], true)),
+        None => split_code_spans(&markdown[start..], &mut segments),
+    }
+    segments
 }
 
-pub fn preprocess_variables(markdown: &str) -> String {
+/// Replaces all variables of `markdown` by their values and removes their definitions. Returns the
+/// resulting text and the defined variables.
+pub fn parse_variables(markdown: &str) -> Result<(String, Variables)> {
+    // without any sigil there is nothing to parse, spare splitting and copying the text
+    if !markdown.contains("'{") && !markdown.contains("<'''") {
+        return Ok((markdown.to_owned(), Variables::new()));
+    }
     let mut var_store = VarStore::new();
-    var_store.parse(&markdown)
+    let text = var_store.parse(markdown)?;
+    Ok((text, var_store.map))
+}
+
+/// Returns the value of the variable `name` used by a `%var:KEY%` placeholder, escaped for html.
+/// Undefined variables are replaced by an empty string.
+pub fn template_variable(name: &str, variables: &Variables) -> String {
+    match variables.get(name) {
+        Some(value) => escape_html(value),
+        None => {
+            warn!("Template variable `{}` is not defined", name);
+            String::new()
+        }
+    }
+}
+
+/// Replaces the quotes around all commands of `markdown` by placeholders, see `restore_commands`.
+pub fn protect_commands(markdown: &str) -> String {
+    RE_CMD
+        .replace_all(markdown, |caps: &Captures| {
+            let command = &caps[0];
+            format!(
+                "{}{}{}",
+                COMMAND_OPEN_PLACEHOLDER,
+                &command[1..command.len() - 1],
+                COMMAND_CLOSE_PLACEHOLDER
+            )
+        })
+        .to_string()
+}
+
+/// Restores the quotes around all commands protected by `protect_commands`.
+pub fn restore_commands(html: &str) -> String {
+    html.replace(COMMAND_OPEN_PLACEHOLDER, "'")
+        .replace(COMMAND_CLOSE_PLACEHOLDER, "'")
+}
+
+/// The element a command refers to
+#[derive(Clone, Copy, PartialEq)]
+enum Element {
+    Parent,
+    Current,
+    Next,
+    Previous,
+}
+
+/// Returns the element a command element type (or a prefix of it) refers to.
+fn element(element_type: &str) -> Option<Element> {
+    match element_type {
+        "p" | "pa" | "par" | "pare" | "paren" | "parent" => Some(Element::Parent),
+        "s" | "se" | "sel" | "self" => Some(Element::Current),
+        "n" | "ne" | "nex" | "next" => Some(Element::Next),
+        "pr" | "pre" | "prev" => Some(Element::Previous),
+        _ => None,
+    }
+}
+
+/// Returns the html attribute a command type (or a prefix of it) refers to.
+fn attribute_name(command_type: &str) -> Option<&'static str> {
+    match command_type {
+        "s" | "st" | "sty" | "styl" | "style" => Some("style"),
+        "c" | "cl" | "cla" | "clas" | "class" => Some("class"),
+        "i" | "id" => Some("id"),
+        _ => None,
+    }
+}
+
+/// Splits the `data` of a command into its attributes, starting with `html_attribute`.
+///
+/// Further `type value` pairs may follow separated by `;`, e.g. `style color: red; class note`.
+/// Any other `;` belongs to the preceding value, as in `style color: red; margin: 0`.
+fn attribute_pairs(html_attribute: &'static str, data: &str) -> Vec<(&'static str, String)> {
+    let mut segments = data.split(';');
+    let mut pairs = vec![(html_attribute, segments.next().unwrap_or("").to_owned())];
+    for segment in segments {
+        let mut words = segment.trim_start().splitn(2, char::is_whitespace);
+        match (words.next().and_then(attribute_name), words.next()) {
+            (Some(attribute), Some(value)) if !value.trim().is_empty() => {
+                pairs.push((attribute, value.to_owned()))
+            }
+            _ => {
+                let value = &mut pairs.last_mut().unwrap().1;
+                value.push(';');
+                value.push_str(segment);
+            }
+        }
+    }
+    pairs
+        .into_iter()
+        .map(|(attribute, value)| (attribute, value.trim().to_owned()))
+        .collect()
+}
+
+/// Sets the html `attribute` of `node` to `data`. Classes are appended to already existing ones.
+fn set_attribute(node: &NodeRef, attribute: &str, data: &str) {
+    if let Some(element_data) = node.as_element() {
+        let mut att = element_data.attributes.borrow_mut();
+        let value = match (attribute, att.get(attribute)) {
+            ("class", Some(classes)) if !classes.is_empty() => format!("{} {}", classes, data),
+            _ => data.to_string(),
+        };
+        att.insert(attribute, value);
+    }
+}
+
+/// Checks that an attribute value of a command cannot be used to inject scripts or to break out of
+/// the attribute.
+fn sanitize(data: &str) -> Result<&str> {
+    let normalized: String = data
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>()
+        .to_lowercase();
+    match ["expression(", "javascript:", "</"]
+        .iter()
+        .find(|pattern| normalized.contains(*pattern))
+    {
+        Some(pattern) => Err(anyhow!("Unsafe value `{}` contains `{}`", data, pattern)),
+        None => Ok(data),
+    }
+}
+
+/// Returns the element a `self` command in the text `node` refers to.
+///
+/// This is the element directly preceding the command, e.g. a link or an emphasis. If the command
+/// does not follow an element (`leading` holds the text in front of the command), the element
+/// containing the command is used.
+fn self_element(node: &NodeRef, leading: &str) -> Option<NodeRef> {
+    match node.previous_sibling() {
+        Some(sibling) if leading.trim().is_empty() && sibling.as_element().is_some() => {
+            Some(sibling)
+        }
+        _ => node.parent(),
+    }
+}
+
+/// Returns the element a `next` (`forward`) or `prev` command in the text `node` refers to.
+///
+/// This is the closest sibling element in that direction, text in between is skipped. Without
+/// such a sibling the siblings of the parent are searched, so a command in a paragraph of its own
+/// refers to the neighbouring block.
+fn sibling_element(node: &NodeRef, forward: bool) -> Option<NodeRef> {
+    let step = |node: &NodeRef| {
+        if forward {
+            node.next_sibling()
+        } else {
+            node.previous_sibling()
+        }
+    };
+    let mut current = node.clone();
+    loop {
+        let mut sibling = step(&current);
+        while let Some(node) = sibling {
+            if node.as_element().is_some() {
+                return Some(node);
+            }
+            sibling = step(&node);
+        }
+        current = current.parent()?;
+        let is_body = current
+            .as_element()
+            .is_none_or(|element_data| &*element_data.name.local == "body");
+        if is_body {
+            return None;
+        }
+    }
+}
+
+/// Returns the paragraph around the text `node` if the command `command` is its sole content.
+///
+/// Such a paragraph only exists because the command was written on a line of its own, it is
+/// removed and the command refers to the following block instead.
+fn lone_command_paragraph(node: &NodeRef, command: &str) -> Option<NodeRef> {
+    let parent = node.parent()?;
+    let is_paragraph = parent
+        .as_element()
+        .is_some_and(|element_data| &*element_data.name.local == "p");
+    if is_paragraph && parent.children().count() == 1 && parent.text_contents().trim() == command {
+        Some(parent)
+    } else {
+        None
+    }
+}
+
+/// Registers the `id` which is about to be set for `node`. Logs a warning if it is already used by
+/// another element, as this is invalid html.
+fn register_id(ids: &mut HashSet<String>, node: &NodeRef, id: &str) {
+    let current = node.as_element().and_then(|element_data| {
+        element_data
+            .attributes
+            .borrow()
+            .get("id")
+            .map(str::to_owned)
+    });
+    if current.as_deref() != Some(id) && !ids.insert(id.to_owned()) {
+        warn!("The id `{}` is used more than once", id);
+    }
+}
+
+/// Returns whether the text `node` is part of code, whose commands are left as they are.
+fn is_code(node: &NodeRef) -> bool {
+    node.ancestors().any(|ancestor| {
+        ancestor
+            .as_element()
+            .is_some_and(|element_data| matches!(&*element_data.name.local, "code" | "pre"))
+    })
+}
+
+/// Returns the abbreviations defined among `variables` as pairs of term and title.
+fn abbreviations(variables: &Variables) -> Vec<(&str, &str)> {
+    let mut abbreviations: Vec<(&str, &str)> = variables
+        .iter()
+        .filter_map(|(key, value)| {
+            key.strip_prefix(ABBREVIATION_PREFIX)
+                .filter(|term| !term.is_empty())
+                .map(|term| (term, value.as_str()))
+        })
+        .collect();
+    // prefer the longest term if one contains another
+    abbreviations.sort_by(|a, b| b.0.len().cmp(&a.0.len()).then(a.0.cmp(b.0)));
+    abbreviations
 }
 
-pub fn apply_commands(html: &str) -> String {
-    let mut change_parents = vec![];
+/// Wraps every standalone occurrence of an abbreviated term in the text of `document` in an
+/// `<abbr>` with its title. Text within code, links and commands is left as it is.
+fn wrap_abbreviations(document: &NodeRef, abbreviations: &[(&str, &str)]) {
+    if abbreviations.is_empty() {
+        return;
+    }
+    // terms only match as whole words
+    let pattern = abbreviations
+        .iter()
+        .map(|(term, _)| {
+            let boundary = |c: Option<char>| {
+                if c.is_some_and(char::is_alphanumeric) {
+                    r"\b"
+                } else {
+                    ""
+                }
+            };
+            format!(
+                "{}{}{}",
+                boundary(term.chars().next()),
+                regex::escape(term),
+                boundary(term.chars().last())
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("|");
+    let re_terms = Regex::new(&pattern).unwrap();
+    let titles: HashMap<&str, &str> = abbreviations.iter().copied().collect();
+
+    let text_nodes: Vec<NodeRef> = document
+        .descendants()
+        .filter(|node| node.as_text().is_some())
+        .collect();
+    for node in text_nodes {
+        let excluded = node.ancestors().any(|ancestor| {
+            ancestor.as_element().is_some_and(|element_data| {
+                ABBREVIATION_EXCLUDED.contains(&&*element_data.name.local)
+            })
+        });
+        let text = node.as_text().unwrap().borrow().clone();
+        if excluded || RE_CMD.is_match(&text) || !re_terms.is_match(&text) {
+            continue;
+        }
+        let mut last = 0;
+        for term in re_terms.find_iter(&text) {
+            node.insert_before(NodeRef::new_text(&text[last..term.start()]));
+            let abbr = format!(
+                "<abbr title=\"{}\">{}</abbr>",
+                escape_html(titles[term.as_str()]),
+                escape_html(term.as_str())
+            );
+            if let Some(abbr) = new_element(&abbr) {
+                node.insert_before(abbr);
+            }
+            last = term.end();
+        }
+        node.insert_before(NodeRef::new_text(&text[last..]));
+        node.detach();
+    }
+}
+
+/// Applies all commands of `html` outside of code and removes them, wraps the abbreviations defined
+/// among `variables`.
+///
+/// Without any command or abbreviation `html` is returned unchanged (apart from escaped sigils),
+/// as parsing and serializing it again may normalize hand-written html.
+pub fn apply_commands(html: &str, variables: &Variables) -> String {
+    let abbreviations = abbreviations(variables);
+    if !RE_CMD.is_match(html) && abbreviations.is_empty() {
+        return html.replace(ESCAPED_SIGIL_PLACEHOLDER, "'{");
+    }
+    let mut lone_paragraphs = vec![];
+    let mut command_texts = vec![];
 
-    let document = kuchiki::parse_html().one(html.clone());
+    let document = kuchiki::parse_html().one(html);
+    let mut ids: HashSet<String> = document
+        .descendants()
+        .filter_map(|node| {
+            node.as_element().and_then(|element_data| {
+                element_data
+                    .attributes
+                    .borrow()
+                    .get("id")
+                    .map(str::to_owned)
+            })
+        })
+        .collect();
     document.descendants().for_each(|node| {
         if let Some(text) = node.as_text() {
+            if is_code(&node) {
+                return;
+            }
+            command_texts.push(node.clone());
             if let Some(capture) = RE_CMD.captures_iter(&text.borrow()).next() {
                 let element_type = &capture["element"];
-                let html_attribute = match &capture["type"] {
-                    "s" | "st" | "sty" | "styl" | "style" => "style",
-                    _ => panic!("HTML attribute `{}` unknown", &capture["type"]),
+                let html_attribute = match attribute_name(&capture["type"]) {
+                    Some(html_attribute) => html_attribute,
+                    None => {
+                        warn!(
+                            "Skipping command `{}`: attribute type `{}` unknown",
+                            &capture[0], &capture["type"]
+                        );
+                        return;
+                    }
                 };
-                let data = &capture["data"];
-                match element_type {
-                    "p" | "pa" | "par" | "pare" | "paren" | "parent" => {
-                        if let Some(parent) = node.parent() {
-                            if let Some(element_data) = parent.as_element() {
-                                let mut att = element_data.attributes.borrow_mut();
-                                att.insert(html_attribute, data.to_string());
-                            }
-                            change_parents.push((parent, data.to_owned()));
-                        }
+                let element = element(element_type);
+                let target = match element {
+                    Some(Element::Parent) => node.parent(),
+                    Some(Element::Current) => {
+                        let leading = &text.borrow()[..capture.get(0).unwrap().start()];
+                        self_element(&node, leading)
                     }
-                    _ => panic!("Element type `{}` unknown", element_type),
+                    Some(Element::Next) => sibling_element(&node, true),
+                    Some(Element::Previous) => sibling_element(&node, false),
+                    None => {
+                        warn!(
+                            "Skipping command `{}`: element type `{}` unknown",
+                            &capture[0], element_type
+                        );
+                        return;
+                    }
+                };
+                let lone_paragraph = lone_command_paragraph(&node, &capture[0]);
+                let target = match lone_paragraph {
+                    Some(_) if element != Some(Element::Previous) => sibling_element(&node, true),
+                    _ => target,
                 };
+                for (html_attribute, data) in attribute_pairs(html_attribute, &capture["data"]) {
+                    if let Err(err) = sanitize(&data) {
+                        warn!("Dropping command `{}`: {}", &capture[0], err);
+                    } else if let Some(target) = &target {
+                        if html_attribute == "id" {
+                            register_id(&mut ids, target, &data);
+                        }
+                        set_attribute(target, html_attribute, &data);
+                    }
+                }
+                lone_paragraphs.extend(lone_paragraph);
             }
         };
     });
 
-    // delte all commands
-    RE_CMD.replace_all(&document.to_string(), "").to_string()
+    // delete all commands outside of code
+    for node in command_texts {
+        if let Some(text) = node.as_text() {
+            let cleaned = RE_CMD.replace_all(&text.borrow(), "").into_owned();
+            text.replace(cleaned);
+        }
+    }
+    for paragraph in lone_paragraphs {
+        paragraph.detach();
+    }
+    wrap_abbreviations(&document, &abbreviations);
+
+    // restore the escaped sigils as literal text
+    document
+        .to_string()
+        .replace(ESCAPED_SIGIL_PLACEHOLDER, "'{")
+}
+
+/// Removes all commands from `text`, e.g. from the text of a heading before it is slugified.
+pub fn strip_commands(text: &str) -> Cow<'_, str> {
+    RE_CMD.replace_all(text, "")
+}
+
+/// Returns the problems of the commands of `markdown` outside of code, for which the commands
+/// would be skipped or dropped by `apply_commands`: unknown element or attribute types and unsafe
+/// values.
+pub fn check_commands(markdown: &str) -> Vec<String> {
+    let mut problems = vec![];
+    for (segment, is_code) in split_code(markdown) {
+        if is_code {
+            continue;
+        }
+        for capture in RE_CMD.captures_iter(segment) {
+            if element(&capture["element"]).is_none() {
+                problems.push(format!(
+                    "Unknown element type `{}` in command `{}`",
+                    &capture["element"], &capture[0]
+                ));
+            }
+            let html_attribute = match attribute_name(&capture["type"]) {
+                Some(html_attribute) => html_attribute,
+                None => {
+                    problems.push(format!(
+                        "Unknown attribute type `{}` in command `{}`",
+                        &capture["type"], &capture[0]
+                    ));
+                    continue;
+                }
+            };
+            for (_, data) in attribute_pairs(html_attribute, &capture["data"]) {
+                if let Err(err) = sanitize(&data) {
+                    problems.push(format!("Unsafe command `{}`: {}", &capture[0], err));
+                }
+            }
+        }
+    }
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_log::{init_logger, warnings_containing};
+
+    fn preprocess_variables(markdown: &str) -> Result<String> {
+        parse_variables(markdown).map(|(text, _)| text)
+    }
+
+    #[test]
+    fn variables_simple() {
+        let input = "<'''red{color: red}'''>'{parent style $red}'";
+        assert_eq!(
+            "'{parent style color: red}'",
+            preprocess_variables(input).unwrap()
+        );
+    }
+
+    #[test]
+    fn variables_chain() {
+        let input = "<'''a{color: $b} b{red}'''>'{parent style $a}'";
+        assert_eq!(
+            "'{parent style color: red}'",
+            preprocess_variables(input).unwrap()
+        );
+    }
+
+    #[test]
+    fn variables_cycle() {
+        let input = "<'''a{$a}'''>'{parent style $a}'";
+        let err = preprocess_variables(input).unwrap_err();
+        assert_eq!("Cyclic variable definition: a -> a", err.to_string());
+    }
+
+    #[test]
+    fn variables_cycle_mutual() {
+        let input = "<'''a{1px solid $b} b{$a} c{red}'''>'{parent style $c}'";
+        let err = preprocess_variables(input).unwrap_err();
+        assert_eq!("Cyclic variable definition: a -> b -> a", err.to_string());
+    }
+
+    #[test]
+    fn variables_dotted() {
+        let input = "<'''theme.fg{red} theme.bg{white}'''>'{parent style $theme.fg $theme.bg}'";
+        assert_eq!(
+            "'{parent style red white}'",
+            preprocess_variables(input).unwrap()
+        );
+    }
+
+    #[test]
+    fn variables_hyphenated() {
+        let input = "<'''main-color{red}'''>'{parent style color: $main-color;}'";
+        assert_eq!(
+            "'{parent style color: red;}'",
+            preprocess_variables(input).unwrap()
+        );
+    }
+
+    #[test]
+    fn variables_multiple_blocks() {
+        let input = "<'''a{red} b{bold}'''>'{parent style $a $b}'\n<'''a{blue}'''>";
+        assert_eq!(
+            "'{parent style blue bold}'\n",
+            preprocess_variables(input).unwrap()
+        );
+    }
+
+    #[test]
+    fn variables_default_unused() {
+        let input = "<'''author{Jane Doe}'''>'{$author:Anonymous}'";
+        assert_eq!("'{Jane Doe}'", preprocess_variables(input).unwrap());
+    }
+
+    #[test]
+    fn variables_default_used() {
+        let input = "'{parent title $author:John Doe}'";
+        assert_eq!(
+            "'{parent title John Doe}'",
+            preprocess_variables(input).unwrap()
+        );
+    }
+
+    #[test]
+    fn variables_colon_separated() {
+        let input = "<'''prop{color} val{red}'''>'{parent style $prop: $val}'";
+        assert_eq!(
+            "'{parent style color: red}'",
+            preprocess_variables(input).unwrap()
+        );
+    }
+
+    #[test]
+    fn variables_missing() {
+        let input = "<'''red{color: red}'''>'{parent style $blue}'";
+        let err = preprocess_variables(input).unwrap_err();
+        assert!(err.to_string().contains("`blue`"));
+    }
+
+    #[test]
+    fn code_segments() {
+        let markdown = "Text `code` and ``a ` b``\n~~~~ rust\n~~~\n~~~~\n```\nend";
+        assert_eq!(
+            vec![
+                ("Text ", false),
+                ("`code`", true),
+                (" and ", false),
+                ("``a ` b``", true),
+                ("\n", false),
+                ("~~~~ rust\n~~~\n~~~~\n", true),
+                ("", false),
+                ("```\nend", true),
+            ],
+            split_code(markdown)
+        );
+        assert_eq!(vec![("a ``b", false)], split_code("a ``b"));
+    }
+
+    #[test]
+    fn variables_in_code() {
+        let input = "<'''red{color: red}'''>'{parent style $red}' `'{$notavar}'`\n\
+                     ```\n<'''red{blue}'''>'{next style $red}'\n```\n\
+                     ~~~\n\\'{parent style $red}'\n~~~\n";
+        assert_eq!(
+            "'{parent style color: red}' `'{$notavar}'`\n\
+             ```\n<'''red{blue}'''>'{next style $red}'\n```\n\
+             ~~~\n\\'{parent style $red}'\n~~~\n",
+            preprocess_variables(input).unwrap()
+        );
+    }
+
+    #[test]
+    fn sanitize_allowed() {
+        assert_eq!("color:red", sanitize("color:red").unwrap());
+    }
+
+    #[test]
+    fn sanitize_rejected() {
+        assert!(sanitize("background: url(JavaScript:alert(1))").is_err());
+        assert!(sanitize("width: expression (alert(1))").is_err());
+        assert!(sanitize("color: red</p><script>").is_err());
+    }
+
+    #[test]
+    fn command_unsafe_dropped() {
+        let html = apply_commands(
+            "<p>Text '{parent style background:url(javascript:x)}'</p>",
+            &Variables::new(),
+        );
+        assert!(html.contains("<p>Text </p>"));
+    }
+
+    #[test]
+    fn command_escaped() {
+        let markdown = "<p>\\'{parent style x}' and '{parent style color: red}'</p>";
+        let html = apply_commands(&preprocess_variables(markdown).unwrap(), &Variables::new());
+        assert!(html.contains("<p style=\"color: red\">'{parent style x}' and </p>"));
+    }
+
+    #[test]
+    fn variables_escaped() {
+        let markdown = "<'''red{color: red}'''>\\'{$red}'";
+        let html = apply_commands(&preprocess_variables(markdown).unwrap(), &Variables::new());
+        assert!(html.contains("'{$red}'"));
+    }
+
+    #[test]
+    fn command_style() {
+        let html = apply_commands("<p>Text '{parent style color: red}'</p>", &Variables::new());
+        assert!(html.contains("<p style=\"color: red\">Text </p>"));
+    }
+
+    #[test]
+    fn command_class() {
+        let html = apply_commands("<p>Text '{parent class my-callout}'</p>", &Variables::new());
+        assert!(html.contains("<p class=\"my-callout\">Text </p>"));
+    }
+
+    #[test]
+    fn command_class_append() {
+        let html = apply_commands(
+            "<div class=\"note\">Text '{p cl my-callout}'</div>",
+            &Variables::new(),
+        );
+        assert!(html.contains("<div class=\"note my-callout\">Text </div>"));
+    }
+
+    #[test]
+    fn command_id() {
+        let html = apply_commands("<p>Text '{parent id intro}'</p>", &Variables::new());
+        assert!(html.contains("<p id=\"intro\">Text </p>"));
+    }
+
+    #[test]
+    fn command_id_duplicate() {
+        init_logger();
+        apply_commands(
+            "<p>One '{parent id twice}'</p><p>Two '{parent id twice}'</p>",
+            &Variables::new(),
+        );
+        assert_eq!(1, warnings_containing("`twice`"));
+    }
+
+    #[test]
+    fn command_unknown_attribute() {
+        init_logger();
+        let html = apply_commands(
+            "<p>One '{parent colour red}'</p><p>Two '{parent class b}'</p>",
+            &Variables::new(),
+        );
+        assert_eq!(1, warnings_containing("attribute type `colour` unknown"));
+        assert!(html.contains("<p>One </p><p class=\"b\">Two </p>"));
+    }
+
+    #[test]
+    fn command_unknown_element() {
+        init_logger();
+        let html = apply_commands(
+            "<p>One '{parnet class a}'</p><p>Two '{parent class b}'</p>",
+            &Variables::new(),
+        );
+        assert_eq!(1, warnings_containing("element type `parnet` unknown"));
+        assert!(html.contains("<p>One </p><p class=\"b\">Two </p>"));
+    }
+
+    #[test]
+    fn abbreviations_wrapped() {
+        let (markdown, variables) =
+            parse_variables("<\'\'\'abbr:HTML{HyperText Markup Language}\'\'\'>").unwrap();
+        assert_eq!("", markdown);
+        let html = apply_commands(
+            "<p>HTML and XHTML</p><p><code>HTML</code> <a href=\"x\">HTML</a></p>",
+            &variables,
+        );
+        assert!(
+            html.contains("<p><abbr title=\"HyperText Markup Language\">HTML</abbr> and XHTML</p>")
+        );
+        assert!(html.contains("<p><code>HTML</code> <a href=\"x\">HTML</a></p>"));
+    }
+
+    #[test]
+    fn command_self() {
+        let html = apply_commands(
+            "<p>Some <em>word</em>'{self style color:red}' here</p>",
+            &Variables::new(),
+        );
+        assert!(html.contains("<p>Some <em style=\"color:red\">word</em> here</p>"));
+    }
+
+    #[test]
+    fn command_self_inline() {
+        let html = apply_commands(
+            "<p>Some <em>word '{s style color:red}'</em> here</p>",
+            &Variables::new(),
+        );
+        assert!(html.contains("<p>Some <em style=\"color:red\">word </em> here</p>"));
+    }
+
+    #[test]
+    fn no_commands_unchanged() {
+        let html = "<p>Raw <img src=\"a.png\" alt=\"a\"/> <span b=\"2\" a=\"1\">html</span></p>\n";
+        assert_eq!(html, apply_commands(html, &Variables::new()));
+    }
+
+    #[test]
+    fn command_next() {
+        let html = apply_commands(
+            "<p>'{next style margin-top:0}'</p>\n<p>Target</p>",
+            &Variables::new(),
+        );
+        assert!(html.contains("<body>\n<p style=\"margin-top:0\">Target</p>"));
+        let html = apply_commands(
+            "<p>Some '{n class big}' <em>word</em></p>",
+            &Variables::new(),
+        );
+        assert!(html.contains("<p>Some  <em class=\"big\">word</em></p>"));
+    }
+
+    #[test]
+    fn command_lone_paragraph() {
+        let html = apply_commands(
+            "<p>Intro</p>\n<p>'{next style width: 100%}'</p>\n<table><tr><td>1</td></tr></table>",
+            &Variables::new(),
+        );
+        assert!(html.contains("<p>Intro</p>\n\n<table style=\"width: 100%\">"));
+        let html = apply_commands(
+            "<p>'{parent class wide}'</p>\n<table></table>",
+            &Variables::new(),
+        );
+        assert!(html.contains("<table class=\"wide\">"));
+        assert!(!html.contains("<p>"));
+    }
+
+    #[test]
+    fn command_in_code() {
+        let html = apply_commands(
+            "<pre><code>'{parent style x}'\n</code></pre>\n<p>Use <code>'{self class y}'</code> '{parent class z}'</p>",
+            &Variables::new(),
+        );
+        assert!(html.contains("<pre><code>'{parent style x}'\n</code></pre>"));
+        assert!(html.contains("<p class=\"z\">Use <code>'{self class y}'</code> </p>"));
+    }
+
+    #[test]
+    fn check_command_problems() {
+        let markdown = "Ok '{parent class note}'\n\nBad '{grandparent class x}' \
+                        '{self colour red}'\n\n'{next style background: url(javascript:x)}'\n\n\
+                        ```\n'{nowhere class y}'\n```\n";
+        assert_eq!(
+            vec![
+                "Unknown element type `grandparent` in command `'{grandparent class x}'`",
+                "Unknown attribute type `colour` in command `'{self colour red}'`",
+                "Unsafe command `'{next style background: url(javascript:x)}'`: Unsafe value \
+                 `background: url(javascript:x)` contains `javascript:`",
+            ],
+            check_commands(markdown)
+        );
+    }
+
+    #[test]
+    fn command_prev() {
+        let html = apply_commands(
+            "<h2>Title</h2>\n<p>'{prev class subtitle}'</p>",
+            &Variables::new(),
+        );
+        assert!(html.contains("<h2 class=\"subtitle\">Title</h2>"));
+    }
+
+    #[test]
+    fn command_multiple_attributes() {
+        let html = apply_commands(
+            "<p>Text '{parent style color: red; margin: 0; class note; id warning}'</p>",
+            &Variables::new(),
+        );
+        assert!(html.contains("style=\"color: red; margin: 0\""));
+        assert!(html.contains("class=\"note\""));
+        assert!(html.contains("id=\"warning\""));
+    }
 }
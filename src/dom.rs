@@ -0,0 +1,14 @@
+use kuchiki::traits::*;
+use kuchiki::NodeRef;
+
+/// Parses the html of a single element.
+pub(crate) fn new_element(html: &str) -> Option<NodeRef> {
+    let element = kuchiki::parse_html()
+        .one(html)
+        .select_first("body > *")
+        .ok()?
+        .as_node()
+        .clone();
+    element.detach();
+    Some(element)
+}
@@ -0,0 +1,136 @@
+use chrono::NaiveDate;
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::reading::plain_text;
+use crate::{escape_html, unescape_html};
+
+/// Number of characters of the body text used as summary of an entry
+const SUMMARY_LENGTH: usize = 200;
+
+lazy_static! {
+    static ref RE_DATE: Regex = Regex::new(r"\d{4}-\d{2}-\d{2}").unwrap();
+}
+
+/// A dated page of the feed.
+#[derive(Debug)]
+pub struct FeedEntry {
+    pub title: String,
+    pub link: String,
+    pub date: NaiveDate,
+    pub summary: String,
+}
+
+/// Returns the date of a page, taken from the `date` of its front matter or else from its
+/// filename `stem`, e.g. `2024-01-05` of a diary page.
+pub fn page_date(front_matter_date: &str, stem: &str) -> Option<NaiveDate> {
+    [front_matter_date, stem].iter().find_map(|text| {
+        RE_DATE
+            .find(text)
+            .and_then(|date| NaiveDate::parse_from_str(date.as_str(), "%Y-%m-%d").ok())
+    })
+}
+
+/// Returns the beginning of the unescaped text of `body_html` to be used as summary.
+pub fn summary(body_html: &str) -> String {
+    let text = unescape_html(&plain_text(body_html));
+    if text.chars().count() > SUMMARY_LENGTH {
+        let text: String = text.chars().take(SUMMARY_LENGTH).collect();
+        format!("{}…", text.trim_end())
+    } else {
+        text
+    }
+}
+
+fn atom_date(date: &NaiveDate) -> String {
+    date.format("%Y-%m-%dT00:00:00Z").to_string()
+}
+
+/// Serializes an Atom feed of `entries`, the newest entry first.
+///
+/// The links of the entries are expected to be absolute, `base_url` identifies the feed itself.
+pub fn build_feed(entries: &[FeedEntry], base_url: &str) -> String {
+    let mut entries: Vec<&FeedEntry> = entries.iter().collect();
+    entries.sort_by_key(|entry| std::cmp::Reverse(entry.date));
+    let base_url = base_url.trim_end_matches('/');
+
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <feed xmlns=\"http://www.w3.org/2005/Atom\">\n",
+    );
+    xml.push_str(&format!(
+        "  <title>{}</title>\n  <id>{}/</id>\n  <link href=\"{}/atom.xml\" rel=\"self\" />\n",
+        escape_html(base_url),
+        escape_html(base_url),
+        escape_html(base_url)
+    ));
+    if let Some(newest) = entries.first() {
+        xml.push_str(&format!(
+            "  <updated>{}</updated>\n",
+            atom_date(&newest.date)
+        ));
+    }
+    for entry in entries {
+        xml.push_str(&format!(
+            "  <entry>\n    <title>{}</title>\n    <link href=\"{}\" />\n    <id>{}</id>\n    \
+             <updated>{}</updated>\n    <summary>{}</summary>\n  </entry>\n",
+            escape_html(&entry.title),
+            escape_html(&entry.link),
+            escape_html(&entry.link),
+            atom_date(&entry.date),
+            escape_html(&entry.summary)
+        ));
+    }
+    xml.push_str("</feed>\n");
+    xml
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(title: &str, date: &str) -> FeedEntry {
+        FeedEntry {
+            title: title.to_owned(),
+            link: format!("https://example.com/{}.html", date),
+            date: NaiveDate::parse_from_str(date, "%Y-%m-%d").unwrap(),
+            summary: "Text".to_owned(),
+        }
+    }
+
+    #[test]
+    fn feed_newest_first() {
+        let entries = vec![entry("Older", "2024-01-05"), entry("Newer", "2024-02-01")];
+        let feed = build_feed(&entries, "https://example.com/");
+        assert!(feed.contains("<updated>2024-02-01T00:00:00Z</updated>\n  <entry>"));
+        let newer = feed.find("<title>Newer</title>").unwrap();
+        let older = feed.find("<title>Older</title>").unwrap();
+        assert!(newer < older);
+        assert!(feed.contains("<link href=\"https://example.com/2024-01-05.html\" />"));
+    }
+
+    #[test]
+    fn date_sources() {
+        let date = |d| NaiveDate::parse_from_str(d, "%Y-%m-%d").ok();
+        assert_eq!(date("2024-03-01"), page_date("2024-03-01", "2024-01-05"));
+        assert_eq!(date("2024-01-05"), page_date("", "2024-01-05"));
+        assert_eq!(None, page_date("", "index"));
+    }
+
+    #[test]
+    fn summary_strips_tags() {
+        assert_eq!(
+            "Title Some text",
+            summary("<h1>Title</h1>\n<p>Some <em>text</em></p>")
+        );
+    }
+
+    #[test]
+    fn summary_escaped_once() {
+        let mut fish = entry("Fish", "2024-01-05");
+        fish.summary = summary("<p>Fish &amp; chips</p>");
+        assert_eq!("Fish & chips", fish.summary);
+        let feed = build_feed(&[fish], "https://example.com/");
+        assert!(feed.contains("<summary>Fish &amp; chips</summary>"));
+    }
+}
@@ -0,0 +1,123 @@
+use kuchiki::traits::*;
+use kuchiki::NodeRef;
+use std::collections::HashSet;
+
+use crate::dom::new_element;
+use crate::escape_html;
+
+/// Wraps the footnote definitions in a `<section class="footnotes">`, headed by `label` unless it
+/// is empty, and adds a `footnote-backref` link from each definition back to its first reference.
+pub fn style_footnotes(html: &str, label: &str) -> String {
+    if !html.contains("footnote-definition") {
+        return html.to_owned();
+    }
+    let document = kuchiki::parse_html().one(html);
+
+    // give the first reference of each footnote an id to link back to
+    let mut referenced = HashSet::new();
+    if let Ok(links) = document.select("sup.footnote-reference > a") {
+        for link in links {
+            let mut attributes = link.attributes.borrow_mut();
+            let name = match attributes
+                .get("href")
+                .and_then(|href| href.strip_prefix('#'))
+            {
+                Some(name) => name.to_owned(),
+                None => continue,
+            };
+            if referenced.insert(name.clone()) {
+                attributes.insert("id", format!("fnref-{}", name));
+            }
+        }
+    }
+
+    let definitions: Vec<NodeRef> = match document.select("div.footnote-definition") {
+        Ok(definitions) => definitions.map(|d| d.as_node().clone()).collect(),
+        Err(_) => return document.to_string(),
+    };
+    // the class name might only appear in the text
+    let first = match definitions.first() {
+        Some(first) => first.clone(),
+        None => return document.to_string(),
+    };
+    let heading = if label.is_empty() {
+        String::new()
+    } else {
+        format!("<h2 class=\"footnotes-label\">{}</h2>", escape_html(label))
+    };
+    let section = match new_element(&format!(
+        "<section class=\"footnotes\">{}</section>",
+        heading
+    )) {
+        Some(section) => section,
+        None => return document.to_string(),
+    };
+    first.insert_before(section.clone());
+
+    for definition in definitions {
+        let name = definition.as_element().and_then(|element_data| {
+            element_data
+                .attributes
+                .borrow()
+                .get("id")
+                .map(str::to_owned)
+        });
+        if let Some(name) = name.filter(|name| referenced.contains(name)) {
+            let backref = new_element(&format!(
+                "<a href=\"#fnref-{}\" class=\"footnote-backref\">↩</a>",
+                escape_html(&name)
+            ));
+            // place the link at the end of the last paragraph if there is one
+            let target = definition
+                .children()
+                .filter(|child| {
+                    child
+                        .as_element()
+                        .is_some_and(|element_data| &*element_data.name.local == "p")
+                })
+                .last()
+                .unwrap_or_else(|| definition.clone());
+            if let Some(backref) = backref {
+                target.append(NodeRef::new_text(" "));
+                target.append(backref);
+            }
+        }
+        section.append(definition);
+    }
+
+    document.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn footnote_section() {
+        let html = "<p>Text<sup class=\"footnote-reference\"><a href=\"#a\">1</a></sup></p>\n\
+                    <div class=\"footnote-definition\" id=\"a\">\
+                    <sup class=\"footnote-definition-label\">1</sup>\n<p>Note A.</p>\n</div>\n";
+        let html = style_footnotes(html, "Notes");
+        assert!(html.contains("<a href=\"#a\" id=\"fnref-a\">1</a>"));
+        assert!(html.contains(
+            "<section class=\"footnotes\"><h2 class=\"footnotes-label\">Notes</h2>\
+             <div class=\"footnote-definition\" id=\"a\">"
+        ));
+        assert!(html.contains(
+            "<p>Note A. <a class=\"footnote-backref\" href=\"#fnref-a\">↩</a></p>\n</div></section>"
+        ));
+    }
+
+    #[test]
+    fn no_footnotes() {
+        assert_eq!("<p>Text</p>", style_footnotes("<p>Text</p>", "Notes"));
+    }
+
+    #[test]
+    fn class_name_in_text() {
+        let html = "<p>Use the <code>footnote-definition</code> class.</p>";
+        let html = style_footnotes(html, "Notes");
+        assert!(html.contains("<p>Use the <code>footnote-definition</code> class.</p>"));
+        assert!(!html.contains("<section"));
+    }
+}
@@ -0,0 +1,119 @@
+use anyhow::{Context, Result};
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::Deserialize;
+use serde_yaml::Value;
+use std::collections::BTreeMap;
+
+lazy_static! {
+    static ref RE_FRONT_MATTER: Regex =
+        Regex::new(r"\A---\r?\n(?P<yaml>(?s).*?)\r?\n(---|\.\.\.)[ \t]*(\r?\n|\z)").unwrap();
+}
+
+/// Metadata of a page, given by a YAML front matter block at the top of the file.
+///
+/// Unknown keys are ignored, missing keys are left empty.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct FrontMatter {
+    pub description: String,
+    pub author: String,
+    pub tags: Vec<String>,
+    pub date: String,
+    pub template: String,
+}
+
+impl FrontMatter {
+    /// Splits `text` into its front matter and the remaining markdown.
+    ///
+    /// If `text` does not start with a front matter block, a default `FrontMatter` and the whole
+    /// `text` are returned.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the front matter is no valid YAML.
+    pub fn split(text: &str) -> Result<(FrontMatter, &str)> {
+        let (yaml, body) = split_yaml(text);
+        let front_matter = if yaml.trim().is_empty() {
+            FrontMatter::default()
+        } else {
+            serde_yaml::from_str(yaml).context("Invalid front matter")?
+        };
+        Ok((front_matter, body))
+    }
+
+    /// Like `split`, but returns all keys of the front matter with their values instead of the
+    /// known ones only.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the front matter is no valid YAML mapping.
+    pub fn split_fields(text: &str) -> Result<(BTreeMap<String, Value>, &str)> {
+        let (yaml, body) = split_yaml(text);
+        let fields = if yaml.trim().is_empty() {
+            BTreeMap::new()
+        } else {
+            serde_yaml::from_str(yaml).context("Invalid front matter")?
+        };
+        Ok((fields, body))
+    }
+}
+
+/// Splits `text` into the YAML of its front matter block, empty if there is none, and the
+/// remaining markdown.
+fn split_yaml(text: &str) -> (&str, &str) {
+    match RE_FRONT_MATTER.captures(text) {
+        Some(caps) => (
+            caps.name("yaml").map_or("", |m| m.as_str()),
+            &text[caps.get(0).unwrap().end()..],
+        ),
+        None => ("", text),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn front_matter() {
+        let text =
+            "---\ndescription: A page\ntags: [rust, wiki]\ndate: 2024-01-05\n---\n# Heading\n";
+        let (front_matter, body) = FrontMatter::split(text).unwrap();
+        assert_eq!("A page", front_matter.description);
+        assert_eq!("", front_matter.author);
+        assert_eq!(vec!["rust", "wiki"], front_matter.tags);
+        assert_eq!("2024-01-05", front_matter.date);
+        assert_eq!("# Heading\n", body);
+    }
+
+    #[test]
+    fn front_matter_missing() {
+        let text = "# Heading\n---\nfoo: bar\n---\n";
+        let (front_matter, body) = FrontMatter::split(text).unwrap();
+        assert_eq!("", front_matter.description);
+        assert_eq!(text, body);
+    }
+
+    #[test]
+    fn front_matter_fields() {
+        let text = "---\ndescription: A page\nstatus: draft\ntags: [rust]\n---\n# Heading\n";
+        let (fields, body) = FrontMatter::split_fields(text).unwrap();
+        assert_eq!(
+            vec!["description", "status", "tags"],
+            fields.keys().collect::<Vec<_>>()
+        );
+        assert_eq!(Value::from("draft"), fields["status"]);
+        assert_eq!(Value::Sequence(vec![Value::from("rust")]), fields["tags"]);
+        assert_eq!("# Heading\n", body);
+        assert!(FrontMatter::split_fields("# Heading\n")
+            .unwrap()
+            .0
+            .is_empty());
+    }
+
+    #[test]
+    fn front_matter_invalid() {
+        assert!(FrontMatter::split("---\ndescription: [a\n---\n").is_err());
+    }
+}
@@ -0,0 +1,327 @@
+use kuchiki::traits::*;
+use kuchiki::NodeRef;
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::collections::HashSet;
+
+use crate::commands::strip_commands;
+
+lazy_static! {
+    static ref RE_HEADING_TAG: Regex = Regex::new(r"(?i)<h[1-6][\s>]").unwrap();
+}
+
+/// Converts a heading text into a fragment identifier, following GitHub's scheme.
+///
+/// The text is lowercased and spaces are replaced by hyphens. Letters and digits of any script as
+/// well as `-` and `_` are kept unchanged, e.g. `Über uns` becomes `über-uns`. Everything else,
+/// such as punctuation, is stripped.
+///
+/// Fragments of links to other vimwiki pages are slugified the same way, so they match the ids of
+/// the headings.
+pub fn slugify(text: &str) -> String {
+    text.trim()
+        .to_lowercase()
+        .chars()
+        .filter_map(|c| match c {
+            ' ' => Some('-'),
+            '-' | '_' => Some(c),
+            c if c.is_alphanumeric() => Some(c),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Assigns a slugified `id` to every heading (`h1`–`h6`) which does not have one yet.
+///
+/// Colliding ids are deduplicated by appending `-1`, `-2`, ... to the slug.
+pub fn add_anchors(html: &str) -> String {
+    // avoid the lossy parse and serialize round-trip if there is nothing to do
+    if !RE_HEADING_TAG.is_match(html) {
+        return html.to_owned();
+    }
+    let document = kuchiki::parse_html().one(html);
+    let headings = match document.select("h1, h2, h3, h4, h5, h6") {
+        Ok(headings) => headings.collect::<Vec<_>>(),
+        Err(_) => return document.to_string(),
+    };
+
+    // ids which are already set must not be generated again
+    let mut used: HashSet<String> = headings
+        .iter()
+        .filter_map(|heading| heading.attributes.borrow().get("id").map(str::to_owned))
+        .collect();
+
+    for heading in headings.iter() {
+        let mut attributes = heading.attributes.borrow_mut();
+        if attributes.contains("id") {
+            continue;
+        }
+        // commands are applied later on, they are no part of the text
+        let slug = slugify(&strip_commands(&heading.text_contents()));
+        let mut id = slug.clone();
+        let mut count = 0;
+        while used.contains(&id) {
+            count += 1;
+            id = format!("{}-{}", slug, count);
+        }
+        attributes.insert("id", id.clone());
+        used.insert(id);
+    }
+
+    document.to_string()
+}
+
+/// Class of the links added to the headings by `link_anchors`
+pub(crate) const ANCHOR_LINK_CLASS: &str = "header-anchor";
+
+/// Appends a link to its own `id` showing `symbol` (e.g. `¶`) to every heading with an id.
+pub fn link_anchors(html: &str, symbol: &str) -> String {
+    if !RE_HEADING_TAG.is_match(html) {
+        return html.to_owned();
+    }
+    let document = kuchiki::parse_html().one(html);
+    let headings = match document.select("h1[id], h2[id], h3[id], h4[id], h5[id], h6[id]") {
+        Ok(headings) => headings.collect::<Vec<_>>(),
+        Err(_) => return document.to_string(),
+    };
+    for heading in headings {
+        let id = heading
+            .attributes
+            .borrow()
+            .get("id")
+            .unwrap_or("")
+            .to_owned();
+        let link = format!(
+            "<a class=\"{}\" href=\"#{}\">{}</a>",
+            ANCHOR_LINK_CLASS,
+            crate::escape_html(&id),
+            crate::escape_html(symbol)
+        );
+        if let Some(link) = crate::dom::new_element(&link) {
+            heading.as_node().append(link);
+        }
+    }
+    document.to_string()
+}
+
+/// Returns the text of `heading` without the symbol of its anchor link, see `link_anchors`.
+fn heading_text(heading: &NodeRef) -> String {
+    heading
+        .descendants()
+        .text_nodes()
+        .filter(|text| {
+            !text.as_node().ancestors().any(|ancestor| {
+                ancestor.as_element().is_some_and(|element_data| {
+                    element_data.attributes.borrow().get("class") == Some(ANCHOR_LINK_CLASS)
+                })
+            })
+        })
+        .map(|text| text.borrow().clone())
+        .collect()
+}
+
+/// A heading of a page, see `headings`.
+#[derive(Debug, PartialEq)]
+pub struct Heading {
+    /// 1 for `h1` up to 6 for `h6`
+    pub level: u32,
+    /// The `id` of the heading, see `add_anchors`
+    pub id: String,
+    pub text: String,
+}
+
+/// Returns all headings (`h1`–`h6`) of `html` in order of appearance.
+pub fn headings(html: &str) -> Vec<Heading> {
+    let document = kuchiki::parse_html().one(html);
+    let headings = match document.select("h1, h2, h3, h4, h5, h6") {
+        Ok(headings) => headings,
+        Err(_) => return vec![],
+    };
+    headings
+        .map(|heading| Heading {
+            level: heading.name.local[1..].parse().unwrap_or(1),
+            id: heading
+                .attributes
+                .borrow()
+                .get("id")
+                .unwrap_or("")
+                .to_owned(),
+            text: heading_text(heading.as_node()).trim().to_owned(),
+        })
+        .collect()
+}
+
+/// Returns the text of the first top-level heading (`h1`) of `html`.
+pub fn first_h1(html: &str) -> Option<String> {
+    let document = kuchiki::parse_html().one(html);
+    let heading = document.select_first("h1").ok()?;
+    let text = heading_text(heading.as_node()).trim().to_owned();
+    if text.is_empty() {
+        None
+    } else {
+        Some(text)
+    }
+}
+
+/// Builds a table of contents as nested `<ul>` lists reflecting the heading hierarchy, wrapped in
+/// a `<div id="toc">`.
+///
+/// Every entry links to the `id` of its heading, therefore `add_anchors` should be applied to
+/// `html` beforehand. If `html` contains less than `min_headings` headings, an empty `String` is
+/// returned.
+pub fn toc(html: &str, min_headings: usize) -> String {
+    let document = kuchiki::parse_html().one(html);
+    let headings = match document.select("h1, h2, h3, h4, h5, h6") {
+        Ok(headings) => headings.collect::<Vec<_>>(),
+        Err(_) => return String::new(),
+    };
+    if headings.is_empty() || headings.len() < min_headings {
+        return String::new();
+    }
+
+    let mut toc = String::new();
+    let mut levels: Vec<u32> = vec![];
+    for heading in headings {
+        let level: u32 = heading.name.local[1..].parse().unwrap_or(1);
+        match levels.last() {
+            Some(&last) if level > last => {
+                // open a nested list inside the current entry
+                toc.push_str("<ul>");
+                levels.push(level);
+            }
+            Some(_) => {
+                while levels.len() > 1 && level < *levels.last().unwrap() {
+                    toc.push_str("</li></ul>");
+                    levels.pop();
+                }
+                toc.push_str("</li>");
+            }
+            None => {
+                toc.push_str("<ul>");
+                levels.push(level);
+            }
+        }
+        let id = heading
+            .attributes
+            .borrow()
+            .get("id")
+            .unwrap_or("")
+            .to_owned();
+        toc.push_str(&format!(
+            "<li><a href=\"#{}\">{}</a>",
+            crate::escape_html(&id),
+            crate::escape_html(heading_text(heading.as_node()).trim())
+        ));
+    }
+    toc.push_str(&"</li></ul>".repeat(levels.len()));
+    format!("<div id=\"toc\">{}</div>", toc)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slug_punctuation() {
+        assert_eq!("whats-new-in-v20", slugify("What's new in v2.0?"));
+    }
+
+    #[test]
+    fn slug_unicode() {
+        assert_eq!("über-uns", slugify("Über uns"));
+        assert_eq!("привет-мир", slugify("Привет, мир!"));
+    }
+
+    #[test]
+    fn anchor_unicode() {
+        let html = add_anchors("<h2>Straße & Grüße</h2>");
+        assert!(html.contains("<h2 id=\"straße--grüße\">"));
+    }
+
+    #[test]
+    fn anchor_simple() {
+        let html = add_anchors("<h2>My Section</h2>");
+        assert!(html.contains("<h2 id=\"my-section\">My Section</h2>"));
+    }
+
+    #[test]
+    fn anchor_punctuation() {
+        let html = add_anchors("<h1>Hello, World!</h1>");
+        assert!(html.contains("<h1 id=\"hello-world\">"));
+    }
+
+    #[test]
+    fn anchor_duplicates() {
+        let html = add_anchors("<h2>Notes</h2><h3>Notes</h3><h2>Notes</h2>");
+        assert!(html.contains("<h2 id=\"notes\">"));
+        assert!(html.contains("<h3 id=\"notes-1\">"));
+        assert!(html.contains("<h2 id=\"notes-2\">"));
+    }
+
+    #[test]
+    fn anchor_without_commands() {
+        let html = add_anchors("<h1>Title '{parent class big}'</h1><h2>A '{self id x}' B</h2>");
+        assert!(html.contains("<h1 id=\"title\">"));
+        assert!(html.contains("<h2 id=\"a--b\">"));
+    }
+
+    #[test]
+    fn anchor_existing_id() {
+        let html = add_anchors("<h2 id=\"custom\">Custom</h2><h2>Custom</h2>");
+        assert!(html.contains("<h2 id=\"custom\">Custom</h2>"));
+        assert!(html.contains("<h2 id=\"custom-1\">Custom</h2>"));
+    }
+
+    #[test]
+    fn toc_nested() {
+        let html = add_anchors("<h1>Intro</h1><h2>Details</h2><h1>Outro</h1>");
+        assert_eq!(
+            "<div id=\"toc\"><ul><li><a href=\"#intro\">Intro</a>\
+             <ul><li><a href=\"#details\">Details</a></li></ul></li>\
+             <li><a href=\"#outro\">Outro</a></li></ul></div>",
+            toc(&html, 2)
+        );
+    }
+
+    #[test]
+    fn anchor_links() {
+        let html = link_anchors(&add_anchors("<h1>Intro</h1><h2>Details</h2>"), "¶");
+        assert!(html.contains(
+            "<h2 id=\"details\">Details<a class=\"header-anchor\" href=\"#details\">¶</a></h2>"
+        ));
+        assert!(toc(&html, 1).contains("<li><a href=\"#details\">Details</a>"));
+        assert_eq!(Some("Intro".to_string()), first_h1(&html));
+    }
+
+    #[test]
+    fn heading_list() {
+        let html = link_anchors(&add_anchors("<h1>Intro</h1><p>Text</p><h3>Deep</h3>"), "¶");
+        assert_eq!(
+            vec![
+                Heading {
+                    level: 1,
+                    id: "intro".to_string(),
+                    text: "Intro".to_string()
+                },
+                Heading {
+                    level: 3,
+                    id: "deep".to_string(),
+                    text: "Deep".to_string()
+                },
+            ],
+            headings(&html)
+        );
+    }
+
+    #[test]
+    fn toc_empty() {
+        assert_eq!("", toc("<p>No headings</p>", 0));
+    }
+
+    #[test]
+    fn toc_single_heading() {
+        let html = add_anchors("<h1>Intro</h1><p>Short page</p>");
+        assert_eq!("", toc(&html, 2));
+        assert!(toc(&html, 1).contains("<a href=\"#intro\">Intro</a>"));
+    }
+}
@@ -0,0 +1,272 @@
+use lazy_static::lazy_static;
+use log::warn;
+use regex::{Captures, Regex};
+use std::collections::BTreeMap;
+use syntect::easy::HighlightLines;
+use syntect::highlighting::{Theme, ThemeSet};
+use syntect::html::{styled_line_to_highlighted_html, IncludeBackground};
+use syntect::parsing::SyntaxSet;
+use syntect::util::LinesWithEndings;
+
+use crate::unescape_html;
+
+lazy_static! {
+    static ref SYNTAX_SET: SyntaxSet = SyntaxSet::load_defaults_newlines();
+    static ref THEME_SET: ThemeSet = ThemeSet::load_defaults();
+    static ref RE_CODE_BLOCK: Regex =
+        Regex::new(r#"<pre><code class="language-(?P<lang>[^"]+)">(?P<code>(?s).*?)</code></pre>"#)
+            .unwrap();
+    static ref RE_CODE_LANGUAGE: Regex =
+        Regex::new(r#"<code class="language-(?P<lang>[^"]+)">"#).unwrap();
+}
+
+/// `highlight.js` theme names (as used by `highlight_theme`) and the `syntect` themes they map to
+const THEME_ALIASES: &[(&str, &str)] = &[
+    ("default", "InspiredGitHub"),
+    ("github", "InspiredGitHub"),
+    ("solarized-dark", "Solarized (dark)"),
+    ("solarized-light", "Solarized (light)"),
+    ("ocean", "base16-ocean.dark"),
+    ("base16-ocean", "base16-ocean.dark"),
+    ("eighties", "base16-eighties.dark"),
+    ("base16-eighties", "base16-eighties.dark"),
+    ("mocha", "base16-mocha.dark"),
+    ("base16-mocha", "base16-mocha.dark"),
+];
+
+/// Themes shipped with `highlight.js` including those of version 10, the themes below `base16/` are
+/// omitted
+pub const HIGHLIGHT_JS_THEMES: &[&str] = &[
+    "default",
+    "a11y-dark",
+    "a11y-light",
+    "agate",
+    "an-old-hope",
+    "androidstudio",
+    "arduino-light",
+    "arta",
+    "ascetic",
+    "atom-one-dark",
+    "atom-one-dark-reasonable",
+    "atom-one-light",
+    "brown-paper",
+    "codepen-embed",
+    "color-brewer",
+    "dark",
+    "devibeans",
+    "docco",
+    "dracula",
+    "far",
+    "felipec",
+    "foundation",
+    "github",
+    "github-dark",
+    "github-dark-dimmed",
+    "gml",
+    "googlecode",
+    "gradient-dark",
+    "gradient-light",
+    "grayscale",
+    "gruvbox-dark",
+    "gruvbox-light",
+    "hybrid",
+    "idea",
+    "intellij-light",
+    "ir-black",
+    "isbl-editor-dark",
+    "isbl-editor-light",
+    "kimbie-dark",
+    "kimbie-light",
+    "lightfair",
+    "lioshi",
+    "magula",
+    "mono-blue",
+    "monokai",
+    "monokai-sublime",
+    "night-owl",
+    "nnfx-dark",
+    "nnfx-light",
+    "nord",
+    "obsidian",
+    "ocean",
+    "panda-syntax-dark",
+    "panda-syntax-light",
+    "paraiso-dark",
+    "paraiso-light",
+    "pojoaque",
+    "purebasic",
+    "qtcreator-dark",
+    "qtcreator-light",
+    "rainbow",
+    "routeros",
+    "school-book",
+    "shades-of-purple",
+    "solarized-dark",
+    "solarized-light",
+    "srcery",
+    "stackoverflow-dark",
+    "stackoverflow-light",
+    "sunburst",
+    "tokyo-night-dark",
+    "tokyo-night-light",
+    "tomorrow",
+    "tomorrow-night",
+    "tomorrow-night-blue",
+    "tomorrow-night-bright",
+    "vs",
+    "vs2015",
+    "xcode",
+    "xt256",
+    "zenburn",
+];
+
+/// Returns the names accepted by `highlight_code`, the `highlight.js` names it maps followed by
+/// the `syntect` default themes.
+pub fn syntect_themes() -> Vec<String> {
+    THEME_ALIASES
+        .iter()
+        .map(|(name, _)| name.to_string())
+        .chain(THEME_SET.themes.keys().cloned())
+        .collect()
+}
+
+/// Returns the number of single character edits turning `a` into `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, char_a) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, char_b) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(char_a != *char_b);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// Returns the `themes` whose names are similar to `name`, the most similar first.
+pub fn similar_themes<'a>(name: &str, themes: &'a [String]) -> Vec<&'a str> {
+    let mut similar: Vec<(usize, &str)> = themes
+        .iter()
+        .map(|theme| (edit_distance(name, theme), theme.as_str()))
+        .filter(|(distance, _)| *distance <= 3)
+        .collect();
+    similar.sort();
+    similar
+        .into_iter()
+        .take(3)
+        .map(|(_, theme)| theme)
+        .collect()
+}
+
+/// Returns the name of the `syntect` theme a `highlight.js` theme name maps to, other names are
+/// returned as they are.
+fn syntect_name(name: &str) -> &str {
+    THEME_ALIASES
+        .iter()
+        .find(|(alias, _)| *alias == name)
+        .map_or(name, |(_, syntect_name)| syntect_name)
+}
+
+/// Maps a `highlight.js` theme name (as used by `highlight_theme`) to a `syntect` theme.
+///
+/// Names of the `syntect` default themes are accepted as well.
+fn theme(name: &str) -> &'static Theme {
+    match THEME_SET.themes.get(syntect_name(name)) {
+        Some(theme) => theme,
+        None => {
+            warn!(
+                "Highlight theme `{}` is unknown to syntect, using `InspiredGitHub`",
+                name
+            );
+            &THEME_SET.themes["InspiredGitHub"]
+        }
+    }
+}
+
+/// Replaces the languages of the code blocks of `html` which are keys of `aliases` by their
+/// values, e.g. `sh` by `bash`.
+pub fn alias_languages(html: String, aliases: &BTreeMap<String, String>) -> String {
+    if aliases.is_empty() {
+        return html;
+    }
+    crate::replace_all_owned(&RE_CODE_LANGUAGE, html, |caps: &Captures| {
+        match aliases.get(&caps["lang"]) {
+            Some(lang) => format!("<code class=\"language-{}\">", crate::escape_html(lang)),
+            None => caps[0].to_owned(),
+        }
+    })
+}
+
+/// Highlights a single code block, returns `None` if the language is unknown.
+fn highlight_block(code: &str, lang: &str, theme: &Theme) -> Option<String> {
+    let syntax = SYNTAX_SET.find_syntax_by_token(lang)?;
+    let mut highlighter = HighlightLines::new(syntax, theme);
+    let mut spans = String::new();
+    for line in LinesWithEndings::from(&unescape_html(code)) {
+        let regions = highlighter.highlight_line(line, &SYNTAX_SET).ok()?;
+        spans.push_str(&styled_line_to_highlighted_html(&regions, IncludeBackground::No).ok()?);
+    }
+    let style = match theme.settings.background {
+        Some(c) => format!(
+            " style=\"background-color:#{:02x}{:02x}{:02x};\"",
+            c.r, c.g, c.b
+        ),
+        None => String::new(),
+    };
+    Some(format!(
+        "<pre{}><code class=\"language-{}\">{}</code></pre>",
+        style, lang, spans
+    ))
+}
+
+/// Highlights all fenced code blocks of `html` server-side with inline-styled spans.
+///
+/// Blocks of unknown languages are left untouched.
+pub fn highlight_code(html: &str, theme_name: &str) -> String {
+    let theme = theme(theme_name);
+    RE_CODE_BLOCK
+        .replace_all(html, |caps: &Captures| {
+            highlight_block(&caps["code"], &caps["lang"], theme)
+                .unwrap_or_else(|| caps[0].to_owned())
+        })
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn highlight_rust() {
+        let html = "<pre><code class=\"language-rust\">fn main() {}\n</code></pre>";
+        let highlighted = highlight_code(html, "default");
+        assert!(highlighted.contains("<code class=\"language-rust\"><span"));
+    }
+
+    #[test]
+    fn syntect_theme_names() {
+        let themes = syntect_themes();
+        assert_eq!("default", themes[0]);
+        assert!(themes.contains(&"InspiredGitHub".to_string()));
+        // every listed name is a known theme
+        for name in themes {
+            assert!(THEME_SET.themes.contains_key(syntect_name(&name)));
+        }
+    }
+
+    #[test]
+    fn similar_theme_names() {
+        assert_eq!(3, edit_distance("kitten", "sitting"));
+        let themes: Vec<String> = HIGHLIGHT_JS_THEMES.iter().map(|s| s.to_string()).collect();
+        assert_eq!(vec!["monokai"], similar_themes("monokia", &themes)[..1]);
+        assert!(similar_themes("completely-different", &themes).is_empty());
+    }
+
+    #[test]
+    fn highlight_unknown_language() {
+        let html = "<pre><code class=\"language-nolang\">foo &lt; bar\n</code></pre>";
+        assert_eq!(html, highlight_code(html, "default"));
+    }
+}
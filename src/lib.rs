@@ -3,59 +3,342 @@
 //! The binary that comes with this crate should be embedded with the VimWiki-Plugin for a seamless
 //! integration.
 
-use anyhow::Result;
-use chrono::Utc;
+use anyhow::{anyhow, Context, Result};
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
 use convert_case::{Case, Casing};
 use directories::ProjectDirs;
 use lazy_static::lazy_static;
 use log::warn;
-use pulldown_cmark::{html, Options, Parser};
-use regex::{Captures, Regex};
+use pulldown_cmark::{html, Event, Options, Parser, Tag, TagEnd};
+use regex::{Captures, NoExpand, Regex, Replacer};
 use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
-use std::io::{Error, ErrorKind, Write};
+use std::io::Write;
 use std::path::{Path, PathBuf};
 
+pub use batch::BatchOptions;
+use commands::Variables;
+pub use feed::{build_feed, FeedEntry};
+use frontmatter::FrontMatter;
+pub use headings::{slugify, Heading};
+pub use links::{extract_links, Link, LinkKind};
+pub use meta::{convert_with_meta, PageMeta};
+pub use search::{build_search_index, SearchEntry};
+pub use sitemap::write_sitemap;
+
+mod batch;
 mod commands;
+mod dom;
+mod emoji;
+mod feed;
+mod footnotes;
+mod frontmatter;
+mod headings;
+mod highlight;
 mod links;
+mod mermaid;
+mod meta;
+mod postprocess;
+mod reading;
+mod search;
+mod sitemap;
+#[cfg(test)]
+mod test_log;
 
-fn get_html(markdown: String) -> String {
-    let mut html_out = String::with_capacity(markdown.len());
-    let parser = Parser::new_ext(
-        &markdown,
-        Options::ENABLE_FOOTNOTES
-            | Options::ENABLE_TABLES
-            | Options::ENABLE_STRIKETHROUGH
-            | Options::ENABLE_TASKLISTS,
-    );
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Replaces the entities inserted by the markdown renderer with their characters.
+fn unescape_html(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Replaces all matches of `re` in `text`, the allocation of `text` is reused if nothing matches.
+fn replace_all_owned<R: Replacer>(re: &Regex, text: String, replacer: R) -> String {
+    let replaced = match re.replace_all(&text, replacer) {
+        Cow::Owned(replaced) => Some(replaced),
+        Cow::Borrowed(_) => None,
+    };
+    replaced.unwrap_or(text)
+}
+
+fn get_html(markdown: String, program_options: &ProgramOptions) -> String {
+    // html is usually about half as long again as its markdown
+    let mut html_out = String::with_capacity(markdown.len() + markdown.len() / 2);
+    let mut in_code_block = false;
+    let parser =
+        Parser::new_ext(&markdown, program_options.markdown_options()).map(|event| match event {
+            Event::Start(Tag::CodeBlock(_)) => {
+                in_code_block = true;
+                event
+            }
+            Event::End(TagEnd::CodeBlock) => {
+                in_code_block = false;
+                event
+            }
+            // inline code is a separate event and thus never expanded
+            Event::Text(text) if program_options.emoji && !in_code_block => {
+                Event::Text(emoji::expand_shortcodes(&text).into_owned().into())
+            }
+            // wrap math for client-side renderers such as KaTeX or MathJax
+            Event::InlineMath(math) => Event::Html(
+                format!(
+                    "<span class=\"math inline\">\\({}\\)</span>",
+                    escape_html(&math)
+                )
+                .into(),
+            ),
+            Event::DisplayMath(math) => Event::Html(
+                format!(
+                    "<div class=\"math display\">\\[{}\\]</div>",
+                    escape_html(&math)
+                )
+                .into(),
+            ),
+            _ => event,
+        });
     html::push_html(&mut html_out, parser);
     html_out
 }
 
+/// Returns the content of the body of `html`, without the surrounding document added when the html
+/// was serialized as a whole.
+fn body_fragment(html: &str) -> String {
+    match RE_DOCUMENT.captures(html) {
+        Some(caps) => caps["body"].to_owned(),
+        None => html.to_owned(),
+    }
+}
+
+/// Format of `%date%` if none or an invalid one is configured
+const DEFAULT_DATE_FORMAT: &str = "%e. %b %Y";
+
+/// Formats `date` with the chrono format string `format`, falling back to `DEFAULT_DATE_FORMAT`
+/// if it is invalid.
+fn format_date(date: &DateTime<Utc>, format: &str) -> String {
+    if StrftimeItems::new(format).any(|item| item == Item::Error) {
+        warn!(
+            "Invalid date format `{}`, using `{}`",
+            format, DEFAULT_DATE_FORMAT
+        );
+        date.format(DEFAULT_DATE_FORMAT).to_string()
+    } else {
+        date.format(format).to_string()
+    }
+}
+
+/// Returns the date of the build used for `%date%`: the configured `fixed_date` (RFC 3339 or
+/// `YYYY-MM-DD`), else the unix timestamp `source_date_epoch` of the `SOURCE_DATE_EPOCH`
+/// environment variable, else the current time. Invalid values are skipped with a warning.
+fn build_date(fixed_date: &str, source_date_epoch: Option<&str>) -> DateTime<Utc> {
+    if !fixed_date.is_empty() {
+        if let Ok(date) = DateTime::parse_from_rfc3339(fixed_date) {
+            return date.with_timezone(&Utc);
+        }
+        if let Ok(date) = NaiveDate::parse_from_str(fixed_date, "%Y-%m-%d") {
+            return Utc.from_utc_datetime(&date.and_hms(0, 0, 0));
+        }
+        warn!("Invalid fixed_date `{}`", fixed_date);
+    }
+    if let Some(epoch) = source_date_epoch {
+        match epoch
+            .trim()
+            .parse()
+            .ok()
+            .and_then(|secs| Utc.timestamp_opt(secs, 0).single())
+        {
+            Some(date) => return date,
+            None => warn!("Invalid SOURCE_DATE_EPOCH `{}`", epoch),
+        }
+    }
+    Utc::now()
+}
+
 fn default_template() -> String {
     "<html>
 <head>
-    <link rel=\"Stylesheet\" type=\"text/css\" href=\"%root_path%style.css\" />
+    <link rel=\"Stylesheet\" type=\"text/css\" href=\"%css%\" />
     <title>%title%</title>
+    <meta name=\"description\" content=\"%description%\" />
     <meta http-equiv=\"Content-Type\" content=\"text/html; charset=utf-8\" />
 
     %pygments%
 </head>
 <body>
+    <a href=\"%index_link%\">%index_label%</a>
     <div class=\"content\">
     %content%
     </div>
+    %backlinks%
 </body>
 </html>"
         .to_owned()
 }
 
+/// Values of the `%name%` placeholders of a template
+type Placeholders<'a> = HashMap<&'a str, String>;
+
+/// Inlines the files of all `%include:PATH%` directives of `template`, PATH is relative to
+/// `template_dir`. Included files may include further files, `included` holds the files being
+/// included to detect cycles. Missing files and cycles are logged and expand to nothing.
+fn resolve_includes(template: &str, template_dir: &Path, included: &mut Vec<PathBuf>) -> String {
+    if !template.contains("%include:") {
+        return template.to_owned();
+    }
+    RE_INCLUDE
+        .replace_all(template, |caps: &Captures| {
+            let path = template_dir.join(caps["path"].trim());
+            let path = path.canonicalize().unwrap_or(path);
+            if included.contains(&path) {
+                warn!("Skipping cyclic include of {}", path.to_str().unwrap_or(""));
+                return String::new();
+            }
+            match fs::read_to_string(&path) {
+                Ok(text) => {
+                    included.push(path);
+                    let text = resolve_includes(&text, template_dir, included);
+                    included.pop();
+                    text
+                }
+                Err(err) => {
+                    warn!("Could not include {}: {}", path.to_str().unwrap_or(""), err);
+                    String::new()
+                }
+            }
+        })
+        .into_owned()
+}
+
+/// Collects the files of all `%include:PATH%` directives of `template` and of the included files
+/// into `found`, PATH is relative to `template_dir`. Missing files are collected as well.
+fn template_includes(template: &str, template_dir: &Path, found: &mut Vec<PathBuf>) {
+    for caps in RE_INCLUDE.captures_iter(template) {
+        let path = template_dir.join(caps["path"].trim());
+        let path = path.canonicalize().unwrap_or(path);
+        if found.contains(&path) {
+            continue;
+        }
+        found.push(path.clone());
+        if let Ok(text) = fs::read_to_string(&path) {
+            template_includes(&text, template_dir, found);
+        }
+    }
+}
+
+/// Keeps the `%if:KEY%...%endif%` sections of `template` whose KEY, the name of a placeholder or
+/// `var:NAME`, has a non-empty value and removes all others. Sections cannot be nested.
+fn resolve_conditionals<'a>(
+    template: &'a str,
+    placeholders: &Placeholders,
+    variables: &Variables,
+) -> Cow<'a, str> {
+    RE_CONDITIONAL.replace_all(template, |caps: &Captures| {
+        let key = &caps["key"];
+        let value = match key.strip_prefix("var:") {
+            Some(name) => variables.get(name),
+            None => placeholders.get(key),
+        };
+        if value.is_some_and(|value| !value.trim().is_empty()) {
+            caps["section"].to_owned()
+        } else {
+            String::new()
+        }
+    })
+}
+
+/// Substitutes all placeholders of `template` in a single pass, so placeholders within the
+/// substituted values (e.g. in the content) are left as they are. Unknown placeholders are kept.
+fn replace_placeholders(
+    template: &str,
+    placeholders: &Placeholders,
+    variables: &Variables,
+) -> String {
+    RE_PLACEHOLDER
+        .replace_all(template, |caps: &Captures| {
+            if let Some(var) = caps.name("var") {
+                return commands::template_variable(var.as_str(), variables);
+            }
+            match placeholders.get(&caps["name"]) {
+                Some(value) => value.to_owned(),
+                None => caps[0].to_owned(),
+            }
+        })
+        .to_string()
+}
+
+/// Where code blocks are highlighted.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HighlightBackend {
+    /// In the browser, e.g. by `highlight.js` configured in the template
+    Client,
+    /// While converting, using `syntect` to emit inline-styled spans
+    Syntect,
+}
+
+/// Name of the per-wiki configuration files, see `ProgramOptions::load_for_input`
+pub(crate) const LOCAL_CONFIG_FILE: &str = ".vimwiki-markdown.toml";
+
+/// Returns the path of the global configuration file.
+fn global_config_file() -> Option<PathBuf> {
+    ProjectDirs::from("com", "tfachmann", "vimwiki-markdown-rs")
+        .map(|proj_dirs| proj_dirs.config_dir().join("config.toml"))
+}
+
 /// All options related to the program such as the `highlighting_theme`.
 ///
-/// It offers options to save and load a `toml` configuration file.
+/// It offers options to save and load a `toml` configuration file. Missing keys fall back to
+/// their default values, so older configuration files stay valid.
 #[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
 pub struct ProgramOptions {
     highlight_theme: String,
+    highlight_backend: HighlightBackend,
+    enable_tables: bool,
+    enable_footnotes: bool,
+    enable_strikethrough: bool,
+    enable_tasklists: bool,
+    enable_smart_punctuation: bool,
+    enable_math: bool,
+    enable_definition_lists: bool,
+    toc_min_headings: usize,
+    check_links: bool,
+    require_alt: bool,
+    title_from_h1: bool,
+    output_extension: String,
+    base_url: String,
+    prefix_root_links: bool,
+    rewrite_links: bool,
+    date_format: String,
+    index_file: String,
+    index_label: String,
+    strip_comments: bool,
+    footnotes_label: String,
+    minify: bool,
+    template_dir: PathBuf,
+    inline_css: bool,
+    words_per_minute: usize,
+    post_process_cmd: String,
+    emoji: bool,
+    wiki_extensions: Vec<String>,
+    fixed_date: String,
+    fragment_only: bool,
+    heading_anchor_symbol: String,
+    mermaid: bool,
+    // tables have to be serialized after all values
+    template_vars: BTreeMap<String, String>,
+    language_aliases: BTreeMap<String, String>,
 }
 
 impl Default for ProgramOptions {
@@ -63,6 +346,40 @@ impl Default for ProgramOptions {
     fn default() -> Self {
         Self {
             highlight_theme: "default".to_string(),
+            highlight_backend: HighlightBackend::Client,
+            enable_tables: true,
+            enable_footnotes: true,
+            enable_strikethrough: true,
+            enable_tasklists: true,
+            enable_smart_punctuation: false,
+            enable_math: true,
+            enable_definition_lists: true,
+            toc_min_headings: 2,
+            check_links: false,
+            require_alt: false,
+            title_from_h1: false,
+            output_extension: "html".to_string(),
+            base_url: String::new(),
+            prefix_root_links: false,
+            rewrite_links: true,
+            date_format: DEFAULT_DATE_FORMAT.to_string(),
+            index_file: "index".to_string(),
+            index_label: "Index".to_string(),
+            strip_comments: false,
+            footnotes_label: String::new(),
+            minify: false,
+            template_dir: PathBuf::new(),
+            inline_css: false,
+            words_per_minute: 200,
+            post_process_cmd: String::new(),
+            emoji: false,
+            wiki_extensions: Vec::new(),
+            fixed_date: String::new(),
+            fragment_only: false,
+            heading_anchor_symbol: String::new(),
+            mermaid: false,
+            template_vars: BTreeMap::new(),
+            language_aliases: BTreeMap::new(),
         }
     }
 }
@@ -73,12 +390,12 @@ impl ProgramOptions {
     /// If the configuration file given by `path` does not exist or is invalid,
     /// `ProgramOptions` with `default` Parameters will be returned.
     pub fn new() -> ProgramOptions {
-        if let Some(proj_dirs) = ProjectDirs::from("com", "tfachmann", "vimwiki-markdown-rs") {
-            let conf_path = Path::new(proj_dirs.config_dir());
-            if !conf_path.is_dir() {
-                fs::create_dir(conf_path).unwrap_or(());
+        if let Some(conf_file) = global_config_file() {
+            if let Some(conf_path) = conf_file.parent() {
+                if !conf_path.is_dir() {
+                    fs::create_dir(conf_path).unwrap_or(());
+                }
             }
-            let conf_file = conf_path.join("config.toml");
             match ProgramOptions::load(&conf_file) {
                 Ok(po) => po,
                 Err(err) => {
@@ -103,14 +420,135 @@ impl ProgramOptions {
         }
     }
 
+    /// Sets whether only the body html is generated, without any template.
+    pub fn set_fragment_only(&mut self, fragment_only: bool) {
+        self.fragment_only = fragment_only;
+    }
+
+    /// Returns the names accepted as `highlight_theme` by the configured `highlight_backend`.
+    pub fn highlight_themes(&self) -> Vec<String> {
+        match self.highlight_backend {
+            HighlightBackend::Client => highlight::HIGHLIGHT_JS_THEMES
+                .iter()
+                .map(|name| name.to_string())
+                .collect(),
+            HighlightBackend::Syntect => highlight::syntect_themes(),
+        }
+    }
+
+    /// Returns the markdown parser `Options` enabled by this configuration.
+    fn markdown_options(&self) -> Options {
+        let mut options = Options::empty();
+        options.set(Options::ENABLE_TABLES, self.enable_tables);
+        options.set(Options::ENABLE_FOOTNOTES, self.enable_footnotes);
+        options.set(Options::ENABLE_STRIKETHROUGH, self.enable_strikethrough);
+        options.set(Options::ENABLE_TASKLISTS, self.enable_tasklists);
+        options.set(
+            Options::ENABLE_SMART_PUNCTUATION,
+            self.enable_smart_punctuation,
+        );
+        options.set(Options::ENABLE_MATH, self.enable_math);
+        options.set(
+            Options::ENABLE_DEFINITION_LIST,
+            self.enable_definition_lists,
+        );
+        options
+    }
+
+    /// Creates a new `ProgramOptions` from the toml configuration file given by `path`, e.g. a
+    /// per-project configuration instead of the one in the default location.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the file cannot be read or is no valid configuration.
+    pub fn from_path(path: &Path) -> Result<ProgramOptions> {
+        ProgramOptions::load(path)
+            .with_context(|| format!("Could not load config {}", path.to_str().unwrap_or("")))
+    }
+
+    /// Creates a new `ProgramOptions` for the page `input_file`.
+    ///
+    /// All `.vimwiki-markdown.toml` files found walking upward from the directory of `input_file`
+    /// are merged over the global configuration, the nearest one wins. Without any of them, this is
+    /// the same as `ProgramOptions::new`.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if a found configuration file cannot be read or is invalid.
+    pub fn load_for_input(input_file: &Path) -> Result<ProgramOptions> {
+        let start = if input_file.is_dir() {
+            Some(input_file)
+        } else {
+            input_file.parent()
+        };
+        let mut local_files: Vec<PathBuf> = start
+            .into_iter()
+            .flat_map(Path::ancestors)
+            .map(|dir| dir.join(LOCAL_CONFIG_FILE))
+            .filter(|file| file.is_file())
+            .collect();
+        if local_files.is_empty() {
+            return Ok(ProgramOptions::new());
+        }
+
+        // the global configuration is the bottom layer, the nearest local one the top layer
+        local_files.reverse();
+        let mut merged = toml::value::Table::new();
+        let global_file = global_config_file().filter(|file| file.is_file());
+        for file in global_file.iter().chain(local_files.iter()) {
+            let data_str = fs::read_to_string(file)?;
+            let table: toml::value::Table = toml::from_str(&data_str)
+                .with_context(|| format!("Invalid config {}", file.to_str().unwrap_or("")))?;
+            merged.extend(table);
+        }
+        let program_options: ProgramOptions = toml::Value::Table(merged).try_into()?;
+        Ok(program_options.check_highlight_theme())
+    }
+
     /// Creates a new `ProgramOptions` from the toml configuration file.
     ///
     /// If the configuration file given by `path` does not exist or is invalid,
     /// `ProgramOptions` with `default` Parameters will be returned.
-    fn load(path: &PathBuf) -> Result<ProgramOptions> {
+    fn load(path: &Path) -> Result<ProgramOptions> {
         let data_str = fs::read_to_string(path)?;
         let data: ProgramOptions = toml::from_str(&data_str)?;
-        Ok(data)
+        Ok(data.check_highlight_theme())
+    }
+
+    /// Checks `highlight_theme` against the themes of the configured backend. An unknown theme is
+    /// replaced by `default` with a warning suggesting similar names.
+    fn check_highlight_theme(mut self) -> Self {
+        let themes = self.highlight_themes();
+        let known = themes.contains(&self.highlight_theme)
+            || (self.highlight_backend == HighlightBackend::Client
+                && self.highlight_theme.starts_with("base16/"));
+        if !known {
+            let suggestions = highlight::similar_themes(&self.highlight_theme, &themes);
+            let hint = if suggestions.is_empty() {
+                String::new()
+            } else {
+                format!(", did you mean `{}`?", suggestions.join("`, `"))
+            };
+            warn!(
+                "Unknown highlight theme `{}`, using `default`{}",
+                self.highlight_theme, hint
+            );
+            self.highlight_theme = "default".to_string();
+        }
+        self
+    }
+
+    /// Returns the text of the template `template_dir/<name>.tpl`, if it exists.
+    fn named_template(&self, name: &str) -> Option<String> {
+        fs::read_to_string(self.named_template_path(name)?).ok()
+    }
+
+    /// Returns the path of the template `template_dir/<name>.tpl`, if a `template_dir` is set.
+    fn named_template_path(&self, name: &str) -> Option<PathBuf> {
+        if self.template_dir.as_os_str().is_empty() {
+            return None;
+        }
+        Some(self.template_dir.join(format!("{}.tpl", name)))
     }
 
     /// Save the `ProgramOptions` to a toml configuration file given with `path`.
@@ -132,120 +570,1657 @@ pub struct VimWikiOptions {
     root_path: PathBuf,
     output_dir: PathBuf,
     input_file: PathBuf,
+    css_file: PathBuf,
+    backlinks: String,
+    template_text: Option<String>,
 }
 
 lazy_static! {
-    static ref RE_LINK: Regex = Regex::new(r"\[(?P<title>.*)\]\((?P<uri>(.)*)\)").unwrap();
+    // `<` is escaped within code, so only raw html comments match
+    static ref RE_COMMENT: Regex = Regex::new(r"(?s)<!--.*?-->\n?").unwrap();
+    // elements whose whitespace is significant
+    static ref RE_PRESERVE: Regex = Regex::new(concat!(
+        r"(?is)<(pre|code|textarea|script|style)\b.*?</(pre|code|textarea|script|style)>",
+        r#"|<div class="mermaid">.*?</div>"#
+    ))
+    .unwrap();
+    static ref RE_WHITESPACE: Regex = Regex::new(r"\s+").unwrap();
+    static ref RE_LINE_BEFORE_TAG: Regex =
+        Regex::new(r"\n</?(?P<name>[A-Za-z][A-Za-z0-9]*)").unwrap();
+    // placeholders of the template, `%name%` or `%var:KEY%`
+    static ref RE_PLACEHOLDER: Regex =
+        Regex::new(r"%(?:var:(?P<var>[^%\s]+)|(?P<name>[A-Za-z_][A-Za-z0-9_]*))%").unwrap();
+    // sections of the template kept only if KEY has a value, `%if:KEY%...%endif%`
+    static ref RE_CONDITIONAL: Regex =
+        Regex::new(r"(?s)%if:(?P<key>[^%\s]+)%(?P<section>.*?)%endif%").unwrap();
+    // files inlined into the template, `%include:PATH%`
+    static ref RE_INCLUDE: Regex = Regex::new(r"%include:(?P<path>[^%\n]+)%").unwrap();
+    // the document around html serialized by kuchiki
+    static ref RE_DOCUMENT: Regex =
+        Regex::new(r"(?s)\A<html><head></head><body>(?P<body>.*)</body></html>\z").unwrap();
+    // the link of the template to the stylesheet
+    static ref RE_STYLESHEET_LINK: Regex =
+        Regex::new(r#"(?i)<link\b[^>]*href="%css%"[^>]*>"#).unwrap();
+    // list items starting with a task list checkbox
+    static ref RE_TASK_ITEM: Regex = Regex::new(
+        r#"<li>(?P<checkbox><input disabled="" type="checkbox"(?P<checked> checked="")?/>)"#
+    )
+    .unwrap();
+}
+
+/// Adds the class `task-done` or `task-pending` to the list items of task lists, depending on
+/// whether their checkbox is checked.
+fn style_tasks(html: String) -> String {
+    replace_all_owned(&RE_TASK_ITEM, html, |caps: &Captures| {
+        let class = if caps.name("checked").is_some() {
+            "task-done"
+        } else {
+            "task-pending"
+        };
+        format!("<li class=\"{}\">{}", class, &caps["checkbox"])
+    })
+}
+
+/// Elements which are not rendered inline, the whitespace around them is insignificant
+const BLOCK_ELEMENTS: &[&str] = &[
+    "address",
+    "article",
+    "aside",
+    "blockquote",
+    "body",
+    "br",
+    "dd",
+    "details",
+    "div",
+    "dl",
+    "dt",
+    "figcaption",
+    "figure",
+    "footer",
+    "form",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "head",
+    "header",
+    "hr",
+    "html",
+    "li",
+    "link",
+    "main",
+    "meta",
+    "nav",
+    "ol",
+    "p",
+    "pre",
+    "script",
+    "section",
+    "style",
+    "summary",
+    "table",
+    "tbody",
+    "td",
+    "tfoot",
+    "th",
+    "thead",
+    "title",
+    "tr",
+    "ul",
+];
+
+/// Returns the lowercase name of the element of `tag`, e.g. `p` of `</p>` or `<p class="x">`.
+fn tag_name(tag: &str) -> String {
+    tag.trim_start_matches(['<', '/'])
+        .chars()
+        .take_while(char::is_ascii_alphanumeric)
+        .collect::<String>()
+        .to_lowercase()
+}
+
+/// Removes the line breaks between two tags of `text` if one of them belongs to a block-level
+/// element. Between inline elements the line break is rendered as a space, so it is kept.
+fn join_lines_between_tags(text: &str) -> String {
+    let is_block = |name: &str| BLOCK_ELEMENTS.contains(&name.to_lowercase().as_str());
+    let mut joined = String::with_capacity(text.len());
+    let mut last = 0;
+    for caps in RE_LINE_BEFORE_TAG.captures_iter(text) {
+        let line = caps.get(0).unwrap().start();
+        let before = &text[..line];
+        if !before.ends_with('>') {
+            continue;
+        }
+        let previous = before.rfind('<').map(|start| tag_name(&before[start..]));
+        if is_block(&caps["name"]) || previous.is_some_and(|name| is_block(&name)) {
+            joined.push_str(&text[last..line]);
+            last = line + 1;
+        }
+    }
+    joined.push_str(&text[last..]);
+    joined
+}
+
+/// Minifies `html` by removing line breaks between block-level tags and collapsing all other
+/// whitespace.
+///
+/// The content of `<pre>`, `<code>`, `<textarea>`, `<script>`, `<style>` and mermaid diagrams is
+/// left untouched.
+pub fn minify_html(html: &str) -> String {
+    let minify = |text: &str, previous: &str, next: &str| {
+        // surrounded by the tags of the preserved elements or of the document
+        let (open, close) = (format!("</{}>", previous), format!("<{}>", next));
+        let text = format!("{}{}{}", open, text, close);
+        let text =
+            RE_WHITESPACE.replace_all(
+                &text,
+                |caps: &Captures| {
+                    if caps[0].contains('\n') {
+                        "\n"
+                    } else {
+                        " "
+                    }
+                },
+            );
+        let text = join_lines_between_tags(&text);
+        text[open.len()..text.len() - close.len()].to_owned()
+    };
+    let mut minified = String::with_capacity(html.len());
+    let mut last = 0;
+    let mut previous = String::from("html");
+    for preserved in RE_PRESERVE.find_iter(html) {
+        let name = tag_name(preserved.as_str());
+        minified.push_str(&minify(&html[last..preserved.start()], &previous, &name));
+        minified.push_str(preserved.as_str());
+        last = preserved.end();
+        previous = name;
+    }
+    minified.push_str(&minify(&html[last..], &previous, "html"));
+    minified.trim().to_owned()
 }
 
 impl VimWikiOptions {
     pub fn new(
         extension: &str,
-        template_file: &PathBuf,
-        root_path: &PathBuf,
-        output_dir: &PathBuf,
-        input_file: &PathBuf,
+        template_file: &Path,
+        root_path: &Path,
+        output_dir: &Path,
+        input_file: &Path,
+        css_file: &Path,
     ) -> Self {
         Self {
             extension: extension.to_string(),
-            template_file: template_file.clone(),
-            root_path: root_path.clone(),
-            output_dir: output_dir.clone(),
-            input_file: input_file.clone(),
+            template_file: template_file.to_path_buf(),
+            root_path: root_path.to_path_buf(),
+            output_dir: output_dir.to_path_buf(),
+            input_file: input_file.to_path_buf(),
+            css_file: css_file.to_path_buf(),
+            backlinks: String::new(),
+            template_text: None,
         }
     }
 
+    /// Uses the given template `text` instead of loading the `template_file`, e.g. a template read
+    /// from stdin.
+    pub fn set_template(&mut self, text: String) {
+        self.template_text = Some(text);
+    }
+
+    /// Returns the file stem of the input file, or `index` if it has none (e.g. `/foo/..`).
     fn stem(&self) -> String {
-        Path::new(&self.input_file)
-            .file_stem()
-            .unwrap()
-            .to_str()
-            .unwrap()
-            .to_owned()
+        match Path::new(&self.input_file).file_stem() {
+            Some(stem) => stem.to_string_lossy().into_owned(),
+            None => "index".to_owned(),
+        }
     }
 
-    /// Returns the path of the html output as `String`
-    pub fn output_filepath(&self) -> String {
+    /// Returns the path of the html output with the configured `output_extension` as `String`
+    pub fn output_filepath(&self, program_options: &ProgramOptions) -> String {
         format!(
-            "{}.html",
-            self.output_dir.join(self.stem()).to_str().unwrap_or("")
+            "{}.{}",
+            self.output_dir.join(self.stem()).to_str().unwrap_or(""),
+            program_options.output_extension
         )
     }
 
-    fn get_template_html(&self, highlightjs_theme: &str) -> String {
-        let text = fs::read_to_string(&self.template_file).unwrap_or_else(|_| default_template());
-        let now = Utc::now();
-        text.replace("%root_path%", &self.root_path.to_str().unwrap_or(""))
-            .replace("%title%", &self.stem().to_case(Case::Title))
-            .replace("%pygments%", "")
-            .replace("%code_theme%", highlightjs_theme)
-            .replace("%date%", &now.format("%e. %b %Y").to_string())
+    /// Returns the directory of the input file relative to `wiki_root`, empty if it is not below.
+    fn relative_dir(&self, wiki_root: &Path) -> &Path {
+        self.input_file
+            .parent()
+            .and_then(|dir| dir.strip_prefix(wiki_root).ok())
+            .unwrap_or_else(|| Path::new(""))
     }
 
-    fn get_body_html(&self) -> Result<String, Error> {
-        // read file to string
-        let text = fs::read_to_string(&self.input_file)?;
+    /// Like `output_filepath`, but the directories of the input file below `wiki_root` are mirrored
+    /// below `output_dir`. Missing directories are created.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if a directory could not be created.
+    pub fn nested_output_filepath(
+        &self,
+        program_options: &ProgramOptions,
+        wiki_root: &Path,
+    ) -> Result<PathBuf> {
+        let output_dir = self.output_dir.join(self.relative_dir(wiki_root));
+        fs::create_dir_all(&output_dir)?;
+        Ok(output_dir.join(format!(
+            "{}.{}",
+            self.stem(),
+            program_options.output_extension
+        )))
+    }
 
-        // pre-process markdown input
-        let text = commands::preprocess_variables(&text);
+    /// Returns the title of the page. It is taken from the first top-level heading of `body_html`
+    /// if `title_from_h1` is set, otherwise (or if there is none) it is derived from the filename.
+    fn title(&self, body_html: &str, program_options: &ProgramOptions) -> String {
+        if program_options.title_from_h1 {
+            if let Some(title) = headings::first_h1(body_html) {
+                return title;
+            }
+        }
+        self.stem().to_case(Case::Title)
+    }
 
-        // fix each link found
-        let text = RE_LINK
-            .replace_all(&text, |caps: &Captures| {
-                links::fix_link(
-                    &caps["title"],
+    /// Returns the template text with all `%include:PATH%` directives resolved against the
+    /// `template_dir`.
+    fn template(&self, program_options: &ProgramOptions, front_matter: &FrontMatter) -> String {
+        resolve_includes(
+            &self.template_source(program_options, front_matter),
+            &program_options.template_dir,
+            &mut vec![],
+        )
+    }
+
+    /// Returns the path of the template file `template_source` reads, `None` for the default
+    /// template and a template given by `set_template`.
+    fn template_path(
+        &self,
+        program_options: &ProgramOptions,
+        front_matter: &FrontMatter,
+    ) -> Option<PathBuf> {
+        let named = |name: &str| {
+            program_options
+                .named_template_path(name)
+                .filter(|path| path.is_file())
+        };
+        if !front_matter.template.is_empty() {
+            if let Some(path) = named(&front_matter.template) {
+                return Some(path);
+            }
+        }
+        if self.template_text.is_some() {
+            return None;
+        }
+        if self.template_file.is_file() {
+            return Some(self.template_file.clone());
+        }
+        named(self.template_file.to_str().unwrap_or(""))
+    }
+
+    /// Returns all files the html of the page is generated from: the input file, the template
+    /// resolved for it and its included files, and the stylesheet. Files which do not exist yet
+    /// are included, e.g. the stylesheet.
+    pub fn source_files(&self, program_options: &ProgramOptions) -> Vec<PathBuf> {
+        let markdown = fs::read_to_string(&self.input_file).unwrap_or_default();
+        let front_matter = FrontMatter::split(&markdown)
+            .map(|(front_matter, _)| front_matter)
+            .unwrap_or_default();
+        let mut files = vec![self.input_file.clone()];
+        files.extend(self.template_path(program_options, &front_matter));
+        template_includes(
+            &self.template_source(program_options, &front_matter),
+            &program_options.template_dir,
+            &mut files,
+        );
+        files.push(self.output_dir.join(self.css_path()));
+        files
+    }
+
+    /// Returns the text of the template as it is stored.
+    ///
+    /// A `template` of the front matter names the template `template_dir/<name>.tpl`. Otherwise
+    /// the template given by `set_template` is used, then `template_file` if it is an existing
+    /// file, or else it names the template as well. Without either, the default template is used.
+    fn template_source(
+        &self,
+        program_options: &ProgramOptions,
+        front_matter: &FrontMatter,
+    ) -> String {
+        if !front_matter.template.is_empty() {
+            match program_options.named_template(&front_matter.template) {
+                Some(text) => return text,
+                None => warn!(
+                    "Template `{}` of {} not found, using the configured template",
+                    front_matter.template,
+                    self.input_file.to_str().unwrap_or("")
+                ),
+            }
+        }
+        if let Some(text) = &self.template_text {
+            return text.clone();
+        }
+        if self.template_file.is_file() {
+            if let Ok(text) = fs::read_to_string(&self.template_file) {
+                return text;
+            }
+        }
+        program_options
+            .named_template(self.template_file.to_str().unwrap_or(""))
+            .unwrap_or_else(default_template)
+    }
+
+    /// Replaces the stylesheet link of `template` by a `<style>` block with the contents of the
+    /// stylesheet at `css_path`, relative to the output directory. Keeps the link if the stylesheet
+    /// cannot be read.
+    fn inline_css(&self, template: &str, css_path: &str) -> String {
+        match fs::read_to_string(self.output_dir.join(css_path)) {
+            Ok(css) => RE_STYLESHEET_LINK
+                .replace_all(
+                    template,
+                    NoExpand(&format!("<style>\n{}\n</style>", css.trim_end())),
+                )
+                .to_string(),
+            Err(err) => {
+                warn!("Could not inline the stylesheet {}: {}", css_path, err);
+                template.to_owned()
+            }
+        }
+    }
+
+    /// Returns the path of the stylesheet relative to the output directory. A relative `css_file`
+    /// is relative to the root, an absolute one (as passed by vimwiki) is made relative to the
+    /// output directory if possible.
+    fn css_path(&self) -> String {
+        let css_file = if self.css_file.as_os_str().is_empty() {
+            Path::new("style.css")
+        } else {
+            self.css_file.as_path()
+        };
+        let path = if css_file.is_absolute() {
+            pathdiff::diff_paths(css_file, &self.output_dir).unwrap_or_else(|| css_file.into())
+        } else {
+            self.root_path.join(css_file)
+        };
+        path.to_str().unwrap_or("").to_owned()
+    }
+
+    /// Returns the template with all placeholders substituted, `placeholders` holds the values
+    /// derived from the body such as `content`. The table of contents is derived from `content`.
+    fn get_template_html<'a>(
+        &self,
+        program_options: &'a ProgramOptions,
+        title: &str,
+        front_matter: &FrontMatter,
+        variables: &Variables,
+        mut placeholders: Placeholders<'a>,
+    ) -> String {
+        let text = self.template(program_options, front_matter);
+        if text.contains("%toc%") {
+            let content = placeholders.get("content").map_or("", String::as_str);
+            let toc = headings::toc(content, program_options.toc_min_headings);
+            placeholders.insert("toc", toc);
+        }
+        let now = build_date(
+            &program_options.fixed_date,
+            std::env::var("SOURCE_DATE_EPOCH").ok().as_deref(),
+        );
+        let mtime = fs::metadata(&self.input_file)
+            .and_then(|meta| meta.modified())
+            .map(|mtime| format_date(&mtime.into(), &program_options.date_format))
+            .unwrap_or_default();
+        let root_path = self.root_path.to_str().unwrap_or("");
+        let css_path = self.css_path();
+        let text = if program_options.inline_css {
+            self.inline_css(&text, &css_path)
+        } else {
+            text
+        };
+        // the slot of the highlighting stylesheet also loads mermaid.js if needed
+        let content = placeholders.get("content").map_or("", String::as_str);
+        let pygments = if program_options.mermaid && mermaid::has_diagrams(content) {
+            mermaid::MERMAID_SCRIPT
+        } else {
+            ""
+        };
+        let index_link = format!(
+            "{}{}.{}",
+            root_path, program_options.index_file, program_options.output_extension
+        );
+        placeholders.extend([
+            ("root_path", root_path.to_owned()),
+            ("css", css_path),
+            ("index_link", index_link),
+            ("index_label", escape_html(&program_options.index_label)),
+            ("title", escape_html(title)),
+            ("pygments", pygments.to_owned()),
+            ("code_theme", program_options.highlight_theme.clone()),
+            ("date", format_date(&now, &program_options.date_format)),
+            ("mtime", mtime),
+            ("description", escape_html(&front_matter.description)),
+            ("author", escape_html(&front_matter.author)),
+            ("tags", escape_html(&front_matter.tags.join(", "))),
+            ("backlinks", self.backlinks.clone()),
+        ]);
+        // custom placeholders cannot override the built-in ones
+        for (name, value) in &program_options.template_vars {
+            placeholders
+                .entry(name.as_str())
+                .or_insert_with(|| value.clone());
+        }
+        let text = resolve_conditionals(&text, &placeholders, variables);
+        replace_placeholders(&text, &placeholders, variables)
+    }
+
+    fn find_broken_links(&self, text: &str, program_options: &ProgramOptions) -> Vec<String> {
+        links::RE_LINK
+            .captures_iter(text)
+            .chain(links::RE_LINK_DEF.captures_iter(text))
+            .filter_map(|caps| {
+                links::find_broken_link(
                     &caps["uri"],
-                    &self.input_file.to_str().unwrap_or(""),
-                    &self.output_dir.to_str().unwrap_or(""),
+                    self.input_file.to_str().unwrap_or(""),
                     &self.extension,
+                    program_options,
                 )
             })
-            .to_string();
+            .collect()
+    }
 
-        // convert to html
-        let html = get_html(text);
+    /// Returns all link targets of the input file which do not exist.
+    ///
+    /// Only `file:` and `local:` links and links to other vimwiki pages are checked.
+    pub fn broken_links(&self, program_options: &ProgramOptions) -> Result<Vec<String>> {
+        let text = fs::read_to_string(&self.input_file)?;
+        Ok(self.find_broken_links(&text, program_options))
+    }
 
-        // apply commands
-        Ok(commands::apply_commands(&html))
+    /// Runs the conversion of the input file up to the body html without writing anything and
+    /// returns all problems found: invalid front matter, undefined or cyclic variables, unknown or
+    /// unsafe commands and broken links, as well as images without alt text if `require_alt` is
+    /// set. Each problem is prefixed with the input file.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the input file cannot be read.
+    pub fn check(&self, program_options: &ProgramOptions) -> Result<Vec<String>> {
+        let markdown = fs::read_to_string(&self.input_file).with_context(|| {
+            format!("Could not read {}", self.input_file.to_str().unwrap_or(""))
+        })?;
+        let mut problems = vec![];
+        let text = match FrontMatter::split(&markdown) {
+            Ok((_, text)) => text,
+            Err(err) => {
+                problems.push(format!("{:#}", err));
+                markdown.as_str()
+            }
+        };
+        let text = match commands::parse_variables(text) {
+            Ok((text, _)) => {
+                problems.extend(commands::check_commands(&text));
+                text
+            }
+            Err(err) => {
+                problems.push(format!("{:#}", err));
+                text.to_owned()
+            }
+        };
+        let text = links::convert_wikilinks(&text);
+        problems.extend(
+            self.find_broken_links(&text, program_options)
+                .into_iter()
+                .map(|link| format!("Broken link: `{}` does not exist", link)),
+        );
+        if program_options.require_alt {
+            problems.extend(
+                links::images_without_alt(&text)
+                    .into_iter()
+                    .map(|uri| format!("Image `{}` has no alt text", uri)),
+            );
+        }
+        Ok(problems
+            .into_iter()
+            .map(|problem| format!("{}: {}", self.input_file.to_str().unwrap_or(""), problem))
+            .collect())
+    }
+
+    fn get_body_html(
+        &self,
+        markdown: &str,
+        program_options: &ProgramOptions,
+    ) -> Result<(FrontMatter, Variables, String)> {
+        // strip the front matter
+        let (front_matter, text) = FrontMatter::split(markdown)?;
+
+        // pre-process markdown input
+        let (text, variables) = commands::parse_variables(text)?;
+        let text = links::convert_wikilinks(&text);
+
+        // report dangling references
+        if program_options.check_links {
+            for link in self.find_broken_links(&text, program_options) {
+                warn!(
+                    "Broken link in {}: `{}` does not exist",
+                    &self.input_file.to_str().unwrap_or(""),
+                    link
+                );
+            }
+        }
+
+        // pass the links through verbatim
+        if !program_options.rewrite_links {
+            let body_html = render_body(text, &variables, program_options);
+            return Ok((front_matter, variables, body_html));
+        }
+
+        // fix each link found
+        let text = replace_all_owned(&links::RE_LINK, text, |caps: &Captures| {
+            let fix = if caps.name("image").is_some() {
+                links::fix_image
+            } else {
+                links::fix_link
+            };
+            fix(
+                &caps["title"],
+                &caps["uri"],
+                self.input_file.to_str().unwrap_or(""),
+                self.output_dir.to_str().unwrap_or(""),
+                &self.extension,
+                program_options,
+            )
+        });
+
+        // fix the uri of each link reference definition
+        let text = replace_all_owned(&links::RE_LINK_DEF, text, |caps: &Captures| {
+            links::fix_link_definition(
+                &caps["label"],
+                &caps["uri"],
+                self.input_file.to_str().unwrap_or(""),
+                self.output_dir.to_str().unwrap_or(""),
+                &self.extension,
+                program_options,
+            )
+        });
+
+        let body_html = render_body(text, &variables, program_options);
+        Ok((front_matter, variables, body_html))
     }
 }
 
+/// Converts the pre-processed markdown to html and applies all html post-processing.
+fn render_body(
+    markdown: String,
+    variables: &Variables,
+    program_options: &ProgramOptions,
+) -> String {
+    // convert to html, commands must not be touched by smart punctuation
+    let html = if program_options.enable_smart_punctuation {
+        commands::restore_commands(&get_html(
+            commands::protect_commands(&markdown),
+            program_options,
+        ))
+    } else {
+        get_html(markdown, program_options)
+    };
+
+    // use the canonical name of the language of code blocks
+    let html = highlight::alias_languages(html, &program_options.language_aliases);
+
+    // mark the state of tasks
+    let html = if program_options.enable_tasklists {
+        style_tasks(html)
+    } else {
+        html
+    };
+
+    // remove html comments
+    let html = if program_options.strip_comments {
+        replace_all_owned(&RE_COMMENT, html, "")
+    } else {
+        html
+    };
+
+    // wrap the footnotes to be styled
+    let html = if program_options.enable_footnotes {
+        footnotes::style_footnotes(&html, &program_options.footnotes_label)
+    } else {
+        html
+    };
+
+    // highlight code blocks
+    let html = match program_options.highlight_backend {
+        HighlightBackend::Syntect => {
+            highlight::highlight_code(&html, &program_options.highlight_theme)
+        }
+        HighlightBackend::Client => html,
+    };
+
+    // add anchors to headings
+    let html = headings::add_anchors(&html);
+    let html = if program_options.heading_anchor_symbol.is_empty() {
+        html
+    } else {
+        headings::link_anchors(&html, &program_options.heading_anchor_symbol)
+    };
+
+    // apply commands
+    let html = commands::apply_commands(&html, variables);
+
+    // the passes parsing the html serialize a whole document
+    let html = body_fragment(&html);
+
+    // let mermaid.js render the diagrams
+    if program_options.mermaid {
+        mermaid::diagrams(html)
+    } else {
+        html
+    }
+}
+
+/// Converts `markdown` to the body html without touching the filesystem.
+///
+/// Variables and commands are processed as usual, links are left as they are since fixing them
+/// requires the location of the input file.
+///
+/// # Errors
+///
+/// Will return `Err` if a used variable is not defined.
+pub fn markdown_to_html(markdown: &str, program_options: &ProgramOptions) -> Result<String> {
+    let (text, variables) = commands::parse_variables(markdown)?;
+    Ok(render_body(
+        links::convert_wikilinks(&text),
+        &variables,
+        program_options,
+    ))
+}
+
 /// Uses `VimWikiOptions` and `ProgramOptions` to load the template and body html. Returns the html String.
-pub fn to_html(
+///
+/// # Errors
+///
+/// Will return `Err` if the input file cannot be read or its content cannot be converted.
+pub fn to_html(wiki_options: &VimWikiOptions, program_options: &ProgramOptions) -> Result<String> {
+    // read file to string
+    let markdown = fs::read_to_string(&wiki_options.input_file).with_context(|| {
+        format!(
+            "Could not read {}",
+            wiki_options.input_file.to_str().unwrap_or("")
+        )
+    })?;
+
+    to_html_from_str(wiki_options, program_options, &markdown)
+}
+
+/// Like `to_html`, but converts the given `markdown` instead of reading `wiki_options.input_file`.
+///
+/// The `input_file` is still used to resolve links and to derive the title.
+///
+/// # Errors
+///
+/// Will return `Err` if the front matter is invalid or a used variable is not defined.
+pub fn to_html_from_str(
     wiki_options: &VimWikiOptions,
     program_options: &ProgramOptions,
-) -> Result<String, Error> {
-    // get template_html
-    let template_html = wiki_options.get_template_html(&program_options.highlight_theme);
-
+    markdown: &str,
+) -> Result<String> {
     // get the html body
-    let body_html = wiki_options.get_body_html().expect("Couldn't load Body");
-    let combined = template_html.replace("%content%", &body_html);
+    let (front_matter, variables, body_html) =
+        wiki_options.get_body_html(markdown, program_options)?;
+
+    if program_options.fragment_only {
+        return Ok(finish_html(body_html, program_options));
+    }
+
+    // get template_html
+    let title = wiki_options.title(&body_html, program_options);
+    let word_count = reading::word_count(&body_html);
+    let reading_time = reading::reading_time(word_count, program_options.words_per_minute);
+    let placeholders = Placeholders::from([
+        ("word_count", word_count.to_string()),
+        ("reading_time", reading_time.to_string()),
+        ("content", body_html),
+    ]);
+    let combined = wiki_options.get_template_html(
+        program_options,
+        &title,
+        &front_matter,
+        &variables,
+        placeholders,
+    );
 
     // return combined html
-    Ok(combined)
+    Ok(finish_html(combined, program_options))
+}
+
+/// Minifies the generated `html` and pipes it through the `post_process_cmd` if configured.
+fn finish_html(html: String, program_options: &ProgramOptions) -> String {
+    let html = if program_options.minify {
+        minify_html(&html)
+    } else {
+        html
+    };
+    if program_options.post_process_cmd.is_empty() {
+        html
+    } else {
+        postprocess::post_process(&html, &program_options.post_process_cmd)
+    }
 }
 
 /// Uses `VimWikiOptions` and `ProgramOptions` to load the template and body html. Also saves the html
-/// file according the `wiki_options.output_filepath()`
+/// file according the `wiki_options.output_filepath(program_options)`
 pub fn to_html_and_save(
     wiki_options: &VimWikiOptions,
     program_options: &ProgramOptions,
 ) -> Result<()> {
     // get html
     let html = to_html(wiki_options, program_options).map_err(|e| {
-        Error::new(
-            ErrorKind::InvalidInput,
-            format!(
-                "Could not create html. The passed options might be compromised: {}",
-                e
-            ),
+        anyhow!(
+            "Could not create html. The passed options might be compromised: {}",
+            e
         )
     })?;
 
     // save file
-    let mut file = fs::File::create(wiki_options.output_filepath())?;
+    let mut file = fs::File::create(wiki_options.output_filepath(program_options))?;
     write!(file, "{}", html)?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes `content` to a temporary input file and returns the options to convert it
+    fn temp_wiki_options(name: &str, content: &str) -> VimWikiOptions {
+        let dir = std::env::temp_dir().join("vimwiki-markdown-rs-tests");
+        fs::create_dir_all(&dir).unwrap();
+        let input_file = dir.join(name).with_extension("wiki");
+        fs::write(&input_file, content).unwrap();
+        VimWikiOptions::new(
+            "wiki",
+            &dir.join("missing_template.tpl"),
+            Path::new("./"),
+            &dir,
+            &input_file,
+            Path::new(""),
+        )
+    }
+
+    #[test]
+    fn task_classes() {
+        let html = get_html(
+            "- [x] Done\n- [ ] Pending\n".to_owned(),
+            &ProgramOptions::default(),
+        );
+        let html = style_tasks(html);
+        assert!(html.contains(
+            "<li class=\"task-done\"><input disabled=\"\" type=\"checkbox\" checked=\"\"/>"
+        ));
+        assert!(
+            html.contains("<li class=\"task-pending\"><input disabled=\"\" type=\"checkbox\"/>")
+        );
+    }
+
+    #[test]
+    fn definition_list() {
+        let html = get_html(
+            "Rust\n: A language\n\nVim\n: An editor\n: A way of life\n".to_owned(),
+            &ProgramOptions::default(),
+        );
+        assert_eq!(
+            "<dl>\n<dt>Rust</dt>\n<dd>A language</dd>\n<dt>Vim</dt>\n<dd>An editor</dd>\n<dd>A way of life</dd>\n</dl>\n",
+            html
+        );
+    }
+
+    #[test]
+    fn math_inline() {
+        let html = get_html(
+            "Einstein said $E=mc^2$".to_owned(),
+            &ProgramOptions::default(),
+        );
+        assert!(html.contains("<span class=\"math inline\">\\(E=mc^2\\)</span>"));
+    }
+
+    #[test]
+    fn math_display() {
+        let html = get_html("$$\\sum_i x_i < 1$$".to_owned(), &ProgramOptions::default());
+        assert!(html.contains("<div class=\"math display\">\\[\\sum_i x_i &lt; 1\\]</div>"));
+    }
+
+    #[test]
+    fn date_format() {
+        let date = DateTime::parse_from_rfc3339("2024-03-05T12:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        assert_eq!("2024-03-05", format_date(&date, "%Y-%m-%d"));
+        assert_eq!(" 5. Mar 2024", format_date(&date, DEFAULT_DATE_FORMAT));
+        assert_eq!(" 5. Mar 2024", format_date(&date, "%Y-%Q"));
+    }
+
+    #[test]
+    fn mtime() {
+        let mut wiki_options = temp_wiki_options("mtime", "# Modified\n");
+        let template_file = wiki_options.input_file.with_extension("tpl");
+        fs::write(&template_file, "<p>Edited %mtime%</p>").unwrap();
+        wiki_options.template_file = template_file;
+        let modified = std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(86_400);
+        fs::File::options()
+            .write(true)
+            .open(&wiki_options.input_file)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        let program_options = ProgramOptions {
+            date_format: "%Y-%m-%d".to_string(),
+            ..ProgramOptions::default()
+        };
+        let html = to_html(&wiki_options, &program_options).unwrap();
+        assert_eq!("<p>Edited 1970-01-02</p>", html);
+
+        // without metadata the placeholder is removed
+        wiki_options.input_file = wiki_options.input_file.with_extension("missing");
+        let html = wiki_options.get_template_html(
+            &program_options,
+            "",
+            &FrontMatter::default(),
+            &Variables::new(),
+            Placeholders::new(),
+        );
+        assert_eq!("<p>Edited </p>", html);
+    }
+
+    #[test]
+    fn template_named() {
+        let mut wiki_options = temp_wiki_options("template_named", "# Named\n");
+        let template_dir = wiki_options.output_dir.join("templates");
+        fs::create_dir_all(&template_dir).unwrap();
+        fs::write(template_dir.join("plain.tpl"), "<main>%content%</main>").unwrap();
+        wiki_options.template_file = PathBuf::from("plain");
+        let program_options = ProgramOptions {
+            template_dir,
+            ..ProgramOptions::default()
+        };
+        let html = to_html(&wiki_options, &program_options).unwrap();
+        assert!(html.starts_with("<main>"));
+        assert!(html.contains("<h1 id=\"named\">Named</h1>"));
+
+        // unknown names fall back to the default template
+        wiki_options.template_file = PathBuf::from("unknown");
+        let html = to_html(&wiki_options, &program_options).unwrap();
+        assert!(html.contains("<title>Template Named</title>"));
+    }
+
+    #[test]
+    fn template_front_matter() {
+        let wiki_options = temp_wiki_options(
+            "template_front_matter",
+            "---\ntemplate: wide\n---\n# Wide\n",
+        );
+        let template_dir = wiki_options.output_dir.join("templates");
+        fs::create_dir_all(&template_dir).unwrap();
+        fs::write(
+            template_dir.join("wide.tpl"),
+            "<main class=\"wide\">%content%</main>",
+        )
+        .unwrap();
+        let program_options = ProgramOptions {
+            template_dir,
+            ..ProgramOptions::default()
+        };
+        let html = to_html(&wiki_options, &program_options).unwrap();
+        assert!(html.starts_with("<main class=\"wide\">"));
+
+        // a missing template falls back to the configured one
+        fs::write(
+            &wiki_options.input_file,
+            "---\ntemplate: missing\n---\n# Wide\n",
+        )
+        .unwrap();
+        let html = to_html(&wiki_options, &program_options).unwrap();
+        assert!(html.starts_with("<html>"));
+    }
+
+    #[test]
+    fn reading_time() {
+        let mut wiki_options = temp_wiki_options("reading_time", &"word ".repeat(250));
+        let template_file = wiki_options.input_file.with_extension("tpl");
+        fs::write(&template_file, "%word_count% words, %reading_time% min").unwrap();
+        wiki_options.template_file = template_file;
+        let program_options = ProgramOptions {
+            words_per_minute: 100,
+            ..ProgramOptions::default()
+        };
+        let html = to_html(&wiki_options, &program_options).unwrap();
+        assert_eq!("250 words, 3 min", html);
+    }
+
+    #[test]
+    fn placeholders_in_content() {
+        let mut wiki_options = temp_wiki_options(
+            "placeholders_in_content",
+            "Use `%title%`, `%content%` and `%unknown%`\n",
+        );
+        let template_file = wiki_options.input_file.with_extension("tpl");
+        fs::write(&template_file, "<h1>%title%</h1>%content%%unknown%").unwrap();
+        wiki_options.template_file = template_file;
+        let html = to_html(&wiki_options, &ProgramOptions::default()).unwrap();
+        assert_eq!(
+            "<h1>Placeholders In Content</h1><p>Use <code>%title%</code>, <code>%content%</code> \
+             and <code>%unknown%</code></p>\n%unknown%",
+            html
+        );
+    }
+
+    #[test]
+    fn template_vars_from_config() {
+        let mut wiki_options = temp_wiki_options("template_vars_from_config", "# Page\n");
+        let template_file = wiki_options.input_file.with_extension("tpl");
+        fs::write(&template_file, "%site_name%: %title%").unwrap();
+        wiki_options.template_file = template_file;
+        let config_file = wiki_options.input_file.with_extension("toml");
+        fs::write(
+            &config_file,
+            "[template_vars]\nsite_name = \"My Wiki\"\ntitle = \"Overridden\"\n",
+        )
+        .unwrap();
+        let program_options = ProgramOptions::from_path(&config_file).unwrap();
+        let html = to_html(&wiki_options, &program_options).unwrap();
+        assert_eq!("My Wiki: Template Vars From Config", html);
+    }
+
+    #[test]
+    fn language_aliases() {
+        let wiki_options =
+            temp_wiki_options("language_aliases", "```sh\nls -l\n```\n\n```rust\nx\n```\n");
+        let config_file = wiki_options.input_file.with_extension("toml");
+        fs::write(
+            &config_file,
+            "[language_aliases]\nsh = \"bash\"\npy = \"python\"\n",
+        )
+        .unwrap();
+        let program_options = ProgramOptions::from_path(&config_file).unwrap();
+        let html = to_html(&wiki_options, &program_options).unwrap();
+        assert!(html.contains("<pre><code class=\"language-bash\">ls -l\n</code></pre>"));
+        assert!(html.contains("<pre><code class=\"language-rust\">x\n</code></pre>"));
+    }
+
+    #[test]
+    fn template_variables() {
+        let mut wiki_options = temp_wiki_options(
+            "template_variables",
+            "<'''title{My <Page>} color{red}'''>\n# Page\n",
+        );
+        let template_file = wiki_options.input_file.with_extension("tpl");
+        fs::write(
+            &template_file,
+            "<title>%var:title%</title>%var:missing%%content%",
+        )
+        .unwrap();
+        wiki_options.template_file = template_file;
+        let html = to_html(&wiki_options, &ProgramOptions::default()).unwrap();
+        assert!(html.starts_with("<title>My &lt;Page&gt;</title>"));
+        assert!(!html.contains("%var:"));
+    }
+
+    #[test]
+    fn template_includes() {
+        crate::test_log::init_logger();
+        let mut wiki_options = temp_wiki_options("template_includes", "# Page\n");
+        let template_dir = wiki_options.output_dir.join("template_includes");
+        fs::create_dir_all(template_dir.join("partials")).unwrap();
+        fs::write(
+            template_dir.join("partials/header.tpl"),
+            "<header>%title% %include:partials/nav.tpl%</header>",
+        )
+        .unwrap();
+        fs::write(
+            template_dir.join("partials/nav.tpl"),
+            "<nav>%index_label%</nav>",
+        )
+        .unwrap();
+        fs::write(
+            template_dir.join("partials/loop.tpl"),
+            "<i>loop</i>%include:partials/loop.tpl%",
+        )
+        .unwrap();
+        wiki_options.set_template(
+            "%include:partials/header.tpl%%include: partials/loop.tpl %\
+             %include:partials/missing.tpl%<main>%content%</main>"
+                .to_string(),
+        );
+        let program_options = ProgramOptions {
+            template_dir: template_dir.clone(),
+            ..ProgramOptions::default()
+        };
+        let html = to_html(&wiki_options, &program_options).unwrap();
+        assert!(html
+            .starts_with("<header>Template Includes <nav>Index</nav></header><i>loop</i><main>"));
+        assert_eq!(
+            1,
+            crate::test_log::warnings_containing("Skipping cyclic include")
+        );
+        assert_eq!(1, crate::test_log::warnings_containing("missing.tpl"));
+    }
+
+    #[test]
+    fn source_files() {
+        let wiki_options = temp_wiki_options("source_files", "---\ntemplate: page\n---\n# Page\n");
+        let template_dir = wiki_options.output_dir.join("source_files");
+        fs::create_dir_all(&template_dir).unwrap();
+        fs::write(template_dir.join("page.tpl"), "%include:nav.tpl%%content%").unwrap();
+        fs::write(template_dir.join("nav.tpl"), "<nav></nav>").unwrap();
+        let program_options = ProgramOptions {
+            template_dir: template_dir.clone(),
+            ..ProgramOptions::default()
+        };
+        let template_dir = template_dir.canonicalize().unwrap();
+        assert_eq!(
+            vec![
+                wiki_options.input_file.clone(),
+                template_dir.join("page.tpl"),
+                template_dir.join("nav.tpl"),
+                wiki_options.output_dir.join("./style.css"),
+            ],
+            wiki_options.source_files(&program_options)
+        );
+    }
+
+    #[test]
+    fn template_conditionals() {
+        let template = "<p>%title%</p>%if:author%<p>By %author%</p>%endif%\
+                        %if:var:color%<p>In %var:color%</p>%endif%%content%";
+        let mut wiki_options = temp_wiki_options(
+            "template_conditionals",
+            "---\nauthor: Jane\n---\n<'''color{red}'''>\n# Page\n",
+        );
+        wiki_options.set_template(template.to_string());
+        let html = to_html(&wiki_options, &ProgramOptions::default()).unwrap();
+        assert!(html.starts_with("<p>Template Conditionals</p><p>By Jane</p><p>In red</p><h1"));
+
+        let mut wiki_options = temp_wiki_options("template_conditionals", "# Page\n");
+        wiki_options.set_template(template.to_string());
+        let html = to_html(&wiki_options, &ProgramOptions::default()).unwrap();
+        assert!(html.starts_with("<p>Template Conditionals</p><h1"));
+        assert!(!html.contains("%endif%") && !html.contains("By"));
+    }
+
+    #[test]
+    fn strip_comments() {
+        let program_options = ProgramOptions {
+            strip_comments: true,
+            ..ProgramOptions::default()
+        };
+        let markdown = "Text <!-- TODO: inline --> here\n\n<!--\nTODO: block\n-->\n\n```html\n<!-- kept -->\n```\n";
+        let html = markdown_to_html(markdown, &program_options).unwrap();
+        assert!(html.contains("<p>Text  here</p>"));
+        assert!(!html.contains("TODO"));
+        assert!(html.contains("&lt;!-- kept --&gt;"));
+
+        let html = markdown_to_html(markdown, &ProgramOptions::default()).unwrap();
+        assert!(html.contains("<!-- TODO: inline -->"));
+    }
+
+    #[test]
+    fn footnotes_label() {
+        let program_options = ProgramOptions {
+            footnotes_label: "Notes".to_string(),
+            ..ProgramOptions::default()
+        };
+        let html = markdown_to_html("Text[^1]\n\n[^1]: A note.\n", &program_options).unwrap();
+        assert!(html.contains(
+            "<section class=\"footnotes\"><h2 class=\"footnotes-label\" id=\"notes\">Notes</h2>"
+        ));
+        assert!(html.contains("class=\"footnote-backref\""));
+    }
+
+    #[test]
+    fn minify() {
+        let html = "<html>\n  <body>\n\n    <p>Some  <em>text</em>\n    here</p>\n\
+                    <pre><code>fn main() {\n    x;\n}\n</code></pre>\n  </body>\n</html>\n";
+        let minified = minify_html(html);
+        assert!(minified.len() < html.len());
+        assert_eq!(
+            "<html><body><p>Some <em>text</em>\nhere</p><pre><code>fn main() {\n    x;\n}\n</code></pre></body></html>",
+            minified
+        );
+    }
+
+    #[test]
+    fn minify_inline_elements() {
+        let html = "<html><body>\n<p><em>one</em>\n<em>two</em></p>\n<p><code>a</code>\n<em>b</em></p>\n</body></html>\n";
+        assert_eq!(
+            "<html><body><p><em>one</em>\n<em>two</em></p><p><code>a</code>\n<em>b</em></p></body></html>",
+            minify_html(html)
+        );
+    }
+
+    #[test]
+    fn mermaid_diagrams() {
+        let markdown = "# Flow\n\n```mermaid\ngraph TD\n    A --> B & C\n```\n";
+        let wiki_options = temp_wiki_options("mermaid_diagrams", markdown);
+        let html = to_html(&wiki_options, &ProgramOptions::default()).unwrap();
+        assert!(html.contains("<code class=\"language-mermaid\">"));
+        assert!(!html.contains("mermaid.esm.min.mjs"));
+
+        let program_options = ProgramOptions {
+            mermaid: true,
+            minify: true,
+            ..ProgramOptions::default()
+        };
+        let html = to_html(&wiki_options, &program_options).unwrap();
+        assert!(html.contains("<div class=\"mermaid\">graph TD\n    A --&gt; B &amp; C\n</div>"));
+        assert!(html.contains(mermaid::MERMAID_SCRIPT));
+    }
+
+    #[test]
+    fn smart_punctuation() {
+        let markdown = "\"Quoted\" -- it's done".to_owned();
+        let html = get_html(markdown.clone(), &ProgramOptions::default());
+        assert!(html.contains("\"Quoted\" -- it's done"));
+        let program_options = ProgramOptions {
+            enable_smart_punctuation: true,
+            ..ProgramOptions::default()
+        };
+        let html = get_html(markdown, &program_options);
+        assert!(html.contains("“Quoted” – it’s done"));
+    }
+
+    #[test]
+    fn smart_punctuation_commands() {
+        let program_options = ProgramOptions {
+            enable_smart_punctuation: true,
+            ..ProgramOptions::default()
+        };
+        let markdown = "<'''red{color: red}'''>\n\"Warning\" '{parent style $red}'\n";
+        let html = markdown_to_html(markdown, &program_options).unwrap();
+        assert!(html.contains("<p style=\"color: red\">“Warning” </p>"));
+    }
+
+    #[test]
+    fn tables_disabled() {
+        let program_options = ProgramOptions {
+            enable_tables: false,
+            ..ProgramOptions::default()
+        };
+        let html = get_html(
+            "| a | b |\n|---|---|\n| 1 | 2 |".to_owned(),
+            &program_options,
+        );
+        assert!(!html.contains("<table>"));
+        assert!(html.contains("| a | b |"));
+    }
+
+    #[test]
+    fn tables_enabled() {
+        let html = get_html(
+            "| a | b |\n|---|---|\n| 1 | 2 |".to_owned(),
+            &ProgramOptions::default(),
+        );
+        assert!(html.contains("<table>"));
+    }
+
+    #[test]
+    fn markdown_to_html_snippet() {
+        let markdown = "<'''red{color: red}'''>\n# Title\n\nWarning '{parent style $red}'\n";
+        let html = markdown_to_html(markdown, &ProgramOptions::default()).unwrap();
+        assert!(html.contains("<h1 id=\"title\">Title</h1>"));
+        assert!(html.contains("<p style=\"color: red\">Warning </p>"));
+        assert!(!html.contains("<html>"));
+    }
+
+    #[test]
+    fn commands_in_fences() {
+        let markdown = "~~~\n'{parent style x}'\n~~~\n\n```\n'{next class y}'\n```\n\n\
+                        Use `'{parent class z}'`\n";
+        let html = markdown_to_html(markdown, &ProgramOptions::default()).unwrap();
+        assert!(html.contains("<pre><code>'{parent style x}'\n</code></pre>"));
+        assert!(html.contains("<pre><code>'{next class y}'\n</code></pre>"));
+        assert!(html.contains("<p>Use <code>'{parent class z}'</code></p>"));
+    }
+
+    #[test]
+    fn fragment_only() {
+        let wiki_options = temp_wiki_options("fragment_only", "# Fragment\n\nBody\n");
+        let mut program_options = ProgramOptions::default();
+        program_options.set_fragment_only(true);
+        let html = to_html(&wiki_options, &program_options).unwrap();
+        assert_eq!("<h1 id=\"fragment\">Fragment</h1>\n<p>Body</p>\n", html);
+        assert!(!html.contains("<html>") && !html.contains("<head>"));
+    }
+
+    #[test]
+    fn large_input() {
+        let markdown: String = (0..5000)
+            .map(|i| {
+                format!(
+                    "## Section {}\n\nText with `code {}` and a [link](page).\n\n",
+                    i, i
+                )
+            })
+            .collect();
+        let wiki_options = temp_wiki_options("large_input", &markdown);
+        let mut program_options = ProgramOptions::default();
+        program_options.set_fragment_only(true);
+        let html = to_html(&wiki_options, &program_options).unwrap();
+        assert_eq!(5000, html.matches("<h2 id=").count());
+        assert!(html.starts_with("<h2 id=\"section-0\">Section 0</h2>"));
+        assert!(html.contains(
+            "<h2 id=\"section-4999\">Section 4999</h2>\n<p>Text with <code>code 4999</code>"
+        ));
+    }
+
+    #[test]
+    fn variables_and_commands() {
+        let markdown = "<'''warn{color: red} size{2em}'''>\n\
+                        # Title\n\n\
+                        Careful '{parent style $warn}' *now* '{self class $size:1em}'\n\n\
+                        '{next id listing}'\n\n\
+                        - item\n";
+        let mut wiki_options = temp_wiki_options("variables_and_commands", markdown);
+        wiki_options.set_template("%content%".to_string());
+        let html = to_html(&wiki_options, &ProgramOptions::default()).unwrap();
+        assert_eq!(
+            "<h1 id=\"title\">Title</h1>\n\
+             <p style=\"color: red\">Careful  <em class=\"2em\">now</em> </p>\n\n\
+             <ul id=\"listing\">\n<li>item</li>\n</ul>\n",
+            html
+        );
+    }
+
+    #[test]
+    fn front_matter_description() {
+        let wiki_options = temp_wiki_options(
+            "front_matter_description",
+            "---\ndescription: All about \"notes\"\n---\n# Notes\n",
+        );
+        let html = to_html(&wiki_options, &ProgramOptions::default()).unwrap();
+        assert!(
+            html.contains("<meta name=\"description\" content=\"All about &quot;notes&quot;\" />")
+        );
+        assert!(!html.contains("description:"));
+    }
+
+    #[test]
+    fn title_from_h1() {
+        let wiki_options = temp_wiki_options("2024-01-01-notes", "# My Notes\n\nText\n");
+        let program_options = ProgramOptions {
+            title_from_h1: true,
+            ..ProgramOptions::default()
+        };
+        let html = to_html(&wiki_options, &program_options).unwrap();
+        assert!(html.contains("<title>My Notes</title>"));
+    }
+
+    #[test]
+    fn title_from_h1_missing() {
+        let wiki_options = temp_wiki_options("2024-01-02-notes", "## Not top-level\n");
+        let program_options = ProgramOptions {
+            title_from_h1: true,
+            ..ProgramOptions::default()
+        };
+        let html = to_html(&wiki_options, &program_options).unwrap();
+        assert!(html.contains("<title>2024 01 02 Notes</title>"));
+    }
+
+    #[test]
+    fn nested_output_filepath() {
+        let dir = std::env::temp_dir().join("vimwiki-markdown-rs-tests/nested_output");
+        let wiki_options = VimWikiOptions::new(
+            "wiki",
+            Path::new(""),
+            Path::new(""),
+            &dir.join("html"),
+            &dir.join("wiki/diary/2024/01.wiki"),
+            Path::new(""),
+        );
+        let output_file = wiki_options
+            .nested_output_filepath(&ProgramOptions::default(), &dir.join("wiki"))
+            .unwrap();
+        assert_eq!(dir.join("html/diary/2024/01.html"), output_file);
+        assert!(dir.join("html/diary/2024").is_dir());
+    }
+
+    #[test]
+    fn relative_dir() {
+        let wiki_options = VimWikiOptions::new(
+            "wiki",
+            Path::new(""),
+            Path::new(""),
+            Path::new("/html"),
+            Path::new("/wiki/diary/2024/01.wiki"),
+            Path::new(""),
+        );
+        assert_eq!(
+            Path::new("diary/2024"),
+            wiki_options.relative_dir(Path::new("/wiki"))
+        );
+        assert_eq!(
+            Path::new(""),
+            wiki_options.relative_dir(Path::new("/notes"))
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn post_process_cmd() {
+        let wiki_options = temp_wiki_options("post_process_cmd", "# Tidy\n");
+        let program_options = ProgramOptions {
+            post_process_cmd: "tr a-z A-Z".to_string(),
+            ..ProgramOptions::default()
+        };
+        to_html_and_save(&wiki_options, &program_options).unwrap();
+        let html = fs::read_to_string(wiki_options.output_filepath(&program_options)).unwrap();
+        assert!(html.contains("<H1 ID=\"TIDY\">TIDY</H1>"));
+    }
+
+    #[test]
+    fn emoji_shortcodes() {
+        let program_options = ProgramOptions {
+            emoji: true,
+            ..ProgramOptions::default()
+        };
+        let markdown = "Done :tada: :unknown_code: `:tada:`\n\n```\n:tada:\n```\n";
+        assert_eq!(
+            "<p>Done 🎉 :unknown_code: <code>:tada:</code></p>\n<pre><code>:tada:\n</code></pre>\n",
+            get_html(markdown.to_string(), &program_options)
+        );
+        assert_eq!(
+            "<p>Done :tada:</p>\n",
+            get_html("Done :tada:".to_string(), &ProgramOptions::default())
+        );
+    }
+
+    #[test]
+    fn template_set() {
+        let mut wiki_options = temp_wiki_options("template_set", "Body\n");
+        wiki_options.template_file = PathBuf::from("missing.tpl");
+        wiki_options.set_template("<title>%title%</title>%content%".to_string());
+        let html = to_html(&wiki_options, &ProgramOptions::default()).unwrap();
+        assert_eq!("<title>Template Set</title><p>Body</p>\n", html);
+    }
+
+    #[test]
+    fn date_reproducible() {
+        let date = |fixed_date, epoch| build_date(fixed_date, epoch).to_rfc3339();
+        assert_eq!("2024-01-05T00:00:00+00:00", date("2024-01-05", Some("0")));
+        assert_eq!(
+            "2024-01-05T09:30:00+00:00",
+            date("2024-01-05T10:30:00+01:00", None)
+        );
+        assert_eq!("2023-11-14T22:13:20+00:00", date("", Some("1700000000")));
+        assert_eq!(
+            "2023-11-14T22:13:20+00:00",
+            date("invalid", Some("1700000000"))
+        );
+        assert!(build_date("", Some("invalid")) > Utc.ymd(2024, 1, 1).and_hms(0, 0, 0));
+
+        let mut wiki_options = temp_wiki_options("date_reproducible", "Body\n");
+        wiki_options.set_template("%date%".to_string());
+        let program_options = ProgramOptions {
+            fixed_date: "2024-01-05".to_string(),
+            date_format: "%Y-%m-%d".to_string(),
+            ..ProgramOptions::default()
+        };
+        assert_eq!(
+            "2024-01-05",
+            to_html(&wiki_options, &program_options).unwrap()
+        );
+    }
+
+    #[test]
+    fn stem_fallback() {
+        let wiki_options = |input_file: &str| {
+            VimWikiOptions::new(
+                "wiki",
+                Path::new(""),
+                Path::new(""),
+                Path::new("/out"),
+                Path::new(input_file),
+                Path::new(""),
+            )
+        };
+        assert_eq!(".wiki", wiki_options("/foo/.wiki").stem());
+        assert_eq!("index", wiki_options("/foo/..").stem());
+        assert_eq!(
+            "/out/index.html",
+            wiki_options("/").output_filepath(&ProgramOptions::default())
+        );
+    }
+
+    #[test]
+    fn rewrite_links_disabled() {
+        let markdown = "[alt](local:../foo.png) and [page](other)\n";
+        let wiki_options = temp_wiki_options("rewrite_links_disabled", markdown);
+        let program_options = ProgramOptions {
+            rewrite_links: false,
+            ..ProgramOptions::default()
+        };
+        let html = to_html(&wiki_options, &program_options).unwrap();
+        assert!(html.contains("<a href=\"local:../foo.png\">alt</a>"));
+        assert!(html.contains("<a href=\"other\">page</a>"));
+        assert!(ProgramOptions::default().rewrite_links);
+    }
+
+    #[test]
+    fn links_in_table() {
+        let wiki_options = temp_wiki_options(
+            "links_in_table",
+            "| a | b |\n|---|---|\n| [x](local:my x.png) | ![y](local:my y.png \"Y (draft)\") |\n",
+        );
+        let html = to_html(&wiki_options, &ProgramOptions::default()).unwrap();
+        assert!(html.contains("<td><a href=\"my%20x.png\">x</a></td>"));
+        assert!(html.contains("<td><img src=\"my%20y.png\" alt=\"y\" title=\"Y (draft)\" /></td>"));
+    }
+
+    #[test]
+    fn css_file() {
+        let mut wiki_options = temp_wiki_options("css_file", "# CSS\n");
+        let html = to_html(&wiki_options, &ProgramOptions::default()).unwrap();
+        assert!(html.contains("href=\"./style.css\""));
+
+        wiki_options.css_file = PathBuf::from("custom.css");
+        let html = to_html(&wiki_options, &ProgramOptions::default()).unwrap();
+        assert!(html.contains("href=\"./custom.css\""));
+    }
+
+    #[test]
+    fn css_file_absolute() {
+        let mut wiki_options = temp_wiki_options("css_file_absolute", "# CSS\n");
+        wiki_options.output_dir = PathBuf::from("/home/user/wiki_html/diary");
+        wiki_options.css_file = PathBuf::from("/home/user/wiki_html/style.css");
+        let html = to_html(&wiki_options, &ProgramOptions::default()).unwrap();
+        assert!(html.contains("href=\"../style.css\""));
+
+        // without a common base it is linked as is
+        wiki_options.output_dir = PathBuf::from("html");
+        let html = to_html(&wiki_options, &ProgramOptions::default()).unwrap();
+        assert!(html.contains("href=\"/home/user/wiki_html/style.css\""));
+    }
+
+    #[test]
+    fn inline_css() {
+        let mut wiki_options = temp_wiki_options("inline_css", "# CSS\n");
+        wiki_options.css_file = PathBuf::from("inline.css");
+        fs::write(
+            wiki_options.output_dir.join("inline.css"),
+            "body { color: $red; }\n",
+        )
+        .unwrap();
+        let program_options = ProgramOptions {
+            inline_css: true,
+            ..ProgramOptions::default()
+        };
+        let html = to_html(&wiki_options, &program_options).unwrap();
+        assert!(html.contains("<style>\nbody { color: $red; }\n</style>"));
+        assert!(!html.contains("inline.css"));
+
+        // a missing stylesheet is linked instead
+        wiki_options.css_file = PathBuf::from("missing.css");
+        let html = to_html(&wiki_options, &program_options).unwrap();
+        assert!(html.contains("href=\"./missing.css\""));
+    }
+
+    #[test]
+    fn index_link() {
+        let wiki_options = temp_wiki_options("index_link", "# Page\n");
+        let html = to_html(&wiki_options, &ProgramOptions::default()).unwrap();
+        assert!(html.contains("<a href=\"./index.html\">Index</a>"));
+
+        let program_options = ProgramOptions {
+            index_file: "home".to_string(),
+            index_label: "Inicio".to_string(),
+            ..ProgramOptions::default()
+        };
+        let html = to_html(&wiki_options, &program_options).unwrap();
+        assert!(html.contains("<a href=\"./home.html\">Inicio</a>"));
+    }
+
+    #[test]
+    fn config_from_path() {
+        let dir = std::env::temp_dir().join("vimwiki-markdown-rs-tests");
+        fs::create_dir_all(&dir).unwrap();
+        let config = dir.join("config_from_path.toml");
+        fs::write(
+            &config,
+            "highlight_theme = \"monokai\"\nenable_tables = false\n",
+        )
+        .unwrap();
+        let program_options = ProgramOptions::from_path(&config).unwrap();
+        assert_eq!("monokai", program_options.highlight_theme);
+        assert!(!program_options.enable_tables);
+        assert!(program_options.enable_footnotes);
+        assert!(ProgramOptions::from_path(&dir.join("missing_config.toml")).is_err());
+    }
+
+    #[test]
+    fn config_unknown_theme() {
+        crate::test_log::init_logger();
+        let dir = std::env::temp_dir().join("vimwiki-markdown-rs-tests");
+        fs::create_dir_all(&dir).unwrap();
+        let config = dir.join("config_unknown_theme.toml");
+        let text = "highlight_theme = \"monokia\"\n";
+        fs::write(&config, text).unwrap();
+        let program_options = ProgramOptions::from_path(&config).unwrap();
+        assert_eq!("default", program_options.highlight_theme);
+        assert_eq!(
+            1,
+            crate::test_log::warnings_containing(
+                "Unknown highlight theme `monokia`, using `default`, did you mean `monokai`"
+            )
+        );
+        assert_eq!(text, fs::read_to_string(&config).unwrap());
+
+        // the themes of the configured backend are accepted
+        fs::write(
+            &config,
+            "highlight_theme = \"Solarized (dark)\"\nhighlight_backend = \"syntect\"\n",
+        )
+        .unwrap();
+        let program_options = ProgramOptions::from_path(&config).unwrap();
+        assert_eq!("Solarized (dark)", program_options.highlight_theme);
+    }
+
+    #[test]
+    fn config_for_input() {
+        let dir = std::env::temp_dir().join("vimwiki-markdown-rs-config-for-input");
+        let nested = dir.join("diary").join("2024");
+        fs::create_dir_all(&nested).unwrap();
+        fs::write(
+            dir.join(LOCAL_CONFIG_FILE),
+            "highlight_theme = \"monokai\"\ntoc_min_headings = 5\n",
+        )
+        .unwrap();
+        fs::write(
+            nested.join(LOCAL_CONFIG_FILE),
+            "highlight_theme = \"solarized-dark\"\n",
+        )
+        .unwrap();
+
+        let program_options = ProgramOptions::load_for_input(&dir.join("index.wiki")).unwrap();
+        assert_eq!("monokai", program_options.highlight_theme);
+        let program_options = ProgramOptions::load_for_input(&nested.join("01.wiki")).unwrap();
+        assert_eq!("solarized-dark", program_options.highlight_theme);
+        assert_eq!(5, program_options.toc_min_headings);
+    }
+
+    #[test]
+    fn missing_input_file() {
+        let dir = std::env::temp_dir().join("vimwiki-markdown-rs-tests");
+        let wiki_options = VimWikiOptions::new(
+            "wiki",
+            &dir.join("missing_template.tpl"),
+            Path::new("./"),
+            &dir,
+            &dir.join("missing_input_file.wiki"),
+            Path::new(""),
+        );
+        assert!(to_html(&wiki_options, &ProgramOptions::default()).is_err());
+        assert!(to_html_and_save(&wiki_options, &ProgramOptions::default()).is_err());
+    }
+
+    #[test]
+    fn check_problems() {
+        let wiki_options = temp_wiki_options(
+            "check_problems",
+            "<'''a{$a}'''>Hello '{parent style $a}' [[Missing Page]]\n",
+        );
+        let input_file = wiki_options.input_file.to_str().unwrap().to_owned();
+        assert_eq!(
+            vec![
+                format!("{}: Cyclic variable definition: a -> a", input_file),
+                format!(
+                    "{}: Broken link: `{}` does not exist",
+                    input_file,
+                    input_file.replace("check_problems", "missing-page")
+                ),
+            ],
+            wiki_options.check(&ProgramOptions::default()).unwrap()
+        );
+
+        let wiki_options = temp_wiki_options("check_problems", "Hi '{here class x}'\n");
+        assert_eq!(
+            vec![format!(
+                "{}: Unknown element type `here` in command `'{{here class x}}'`",
+                input_file
+            )],
+            wiki_options.check(&ProgramOptions::default()).unwrap()
+        );
+
+        let wiki_options = temp_wiki_options("check_problems", "# Fine\n\n![](logo.png)\n");
+        assert!(wiki_options
+            .check(&ProgramOptions::default())
+            .unwrap()
+            .is_empty());
+        let program_options = ProgramOptions {
+            require_alt: true,
+            ..ProgramOptions::default()
+        };
+        assert_eq!(
+            vec![format!("{}: Image `logo.png` has no alt text", input_file)],
+            wiki_options.check(&program_options).unwrap()
+        );
+    }
+
+    #[test]
+    fn undefined_variable() {
+        let wiki_options = temp_wiki_options("undefined_variable", "Hello '{style $nobody}'\n");
+        assert!(to_html(&wiki_options, &ProgramOptions::default()).is_err());
+    }
+
+    #[test]
+    fn output_extension() {
+        let wiki_options = temp_wiki_options("output_extension", "[Other](output_extension)\n");
+        let program_options = ProgramOptions {
+            output_extension: "htm".to_string(),
+            ..ProgramOptions::default()
+        };
+        assert!(wiki_options
+            .output_filepath(&program_options)
+            .ends_with("/output_extension.htm"));
+        let html = to_html(&wiki_options, &program_options).unwrap();
+        assert!(html.contains("<a href=\"output_extension.htm\">Other</a>"));
+    }
+}
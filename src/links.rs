@@ -1,23 +1,45 @@
+use lazy_static::lazy_static;
+use log::warn;
 use path_clean::PathClean;
 use pathdiff::diff_paths;
-use regex::Regex;
+use regex::{Captures, Regex};
+use std::borrow::Cow;
 use std::path::{Path, PathBuf};
 
-trait PathSpaces<T> {
-    fn handle_spaces(&self) -> T;
+use crate::commands::split_code;
+use crate::headings::slugify;
+use crate::ProgramOptions;
+
+lazy_static! {
+    // brackets and parentheses may be nested once, e.g. an image within a link or `(draft)` in a
+    // title, so several links on one line are matched separately
+    pub(crate) static ref RE_LINK: Regex = Regex::new(
+        r"(?P<image>!)?\[(?P<title>(?:[^\[\]]|\[[^\[\]]*\])*)\]\((?P<uri>(?:[^()]|\([^()]*\))*)\)"
+    )
+    .unwrap();
+    // footnote definitions (`[^label]: text`) are excluded
+    pub(crate) static ref RE_LINK_DEF: Regex =
+        Regex::new(r"(?m)^ {0,3}\[(?P<label>[^\]^][^\]]*)\]:[ \t]*(?P<uri>\S.*?)[ \t]*$").unwrap();
+    // a size hint at the end of an image uri, e.g. `=300x`, `=x200` or `=300x200`
+    static ref RE_IMAGE_SIZE: Regex =
+        Regex::new(r"\s+=(?P<width>\d*)x(?P<height>\d*)\s*$").unwrap();
+    // the whitespace separating the title of a link from its uri
+    static ref RE_TITLE: Regex = Regex::new(r#"\s+""#).unwrap();
+    static ref RE_WIKILINK: Regex =
+        Regex::new(r"\[\[(?P<target>[^\]|]+)(\|(?P<label>[^\]]+))?\]\]").unwrap();
 }
 
-/// PathSpaces implemented for PathBuf
-impl PathSpaces<PathBuf> for PathBuf {
-    fn handle_spaces(&self) -> PathBuf {
-        PathBuf::from(handle_spaces(self.to_str().unwrap_or("")))
-    }
+/// Characters which are percent-encoded in paths, a `%` is encoded unless it starts an escape
+const RESERVED_PATH_CHARS: [char; 6] = [' ', '#', '?', '"', '<', '>'];
+
+trait PercentEncode<T> {
+    fn percent_encode(&self) -> T;
 }
 
-/// PathSpaces implemented for String
-impl PathSpaces<String> for String {
-    fn handle_spaces(&self) -> String {
-        handle_spaces(self)
+/// PercentEncode implemented for PathBuf
+impl PercentEncode<PathBuf> for PathBuf {
+    fn percent_encode(&self) -> PathBuf {
+        PathBuf::from(percent_encode_path(self.to_str().unwrap_or("")))
     }
 }
 
@@ -25,18 +47,40 @@ fn handle_spaces(path: &str) -> String {
     path.replace(' ', "%20")
 }
 
-fn handle_fragment(uri: &str) -> (&str, Option<&str>) {
-    let split: Vec<&str> = uri.split('#').collect();
-    match split.len() {
-        1 => (split[0], None),
-        2 => (split[0], Some(split[1])),
-        _ => (&uri[..], None),
+fn percent_encode_path(path: &str) -> String {
+    let mut encoded = String::with_capacity(path.len());
+    for (i, c) in path.char_indices() {
+        let is_escape = c == '%'
+            && path
+                .get(i + 1..i + 3)
+                .is_some_and(|hex| hex.chars().all(|h| h.is_ascii_hexdigit()));
+        if (c == '%' && !is_escape) || RESERVED_PATH_CHARS.contains(&c) {
+            encoded.push_str(&format!("%{:02X}", c as u32));
+        } else {
+            encoded.push(c);
+        }
     }
+    encoded
 }
 
-fn fix_link_vimwiki(uri: &str) -> String {
+fn handle_fragment(uri: &str) -> (&str, Option<&str>) {
+    // only the first `#` starts the fragment, all others belong to it
+    let mut split = uri.splitn(2, '#');
+    (split.next().unwrap_or(""), split.next())
+}
+
+/// Points a link to a vimwiki page to its output file, a directory such as `diary/` to the output of
+/// its `index_file`.
+fn fix_link_vimwiki(uri: &str, output_extension: &str, index_file: &str) -> String {
     let (url_raw, fragment) = handle_fragment(&uri);
-    // convert (wiki extension) to .html
+    let index_path;
+    let url_raw = if url_raw.ends_with('/') {
+        index_path = format!("{}{}", url_raw, index_file);
+        &index_path
+    } else {
+        url_raw
+    };
+    // convert (wiki extension) to the output extension
     let tmp = Path::new(&url_raw);
     let url_raw = tmp
         .parent()
@@ -46,24 +90,43 @@ fn fix_link_vimwiki(uri: &str) -> String {
         .unwrap()
         .to_owned();
     match fragment {
-        Some(fragment) => format!("{}.html#{}", url_raw, fragment.to_string().handle_spaces()),
-        None => format!("{}.html", url_raw),
+        // the fragment names a heading, its id is the slug of the heading
+        Some(fragment) => format!("{}.{}#{}", url_raw, output_extension, slugify(fragment)),
+        None => format!("{}.{}", url_raw, output_extension),
     }
 }
 
-fn fix_link_rest(uri: &str, input_dir: &Path, output_dir: &Path) -> String {
-    fn handle_title(uri: &str) -> (&str, Option<&str>) {
-        // split uri in (url, title)
-        let re_title = Regex::new(r#"\s+""#).unwrap();
-        let split: Vec<&str> = re_title.split(&uri).collect();
-        match split.len() {
-            1 => (split[0], None),
-            2 => (split[0], Some(split[1])),
-            _ => (&uri[..], None),
-        }
+fn handle_title(uri: &str) -> (&str, Option<&str>) {
+    // split uri in (url, title)
+    let split: Vec<&str> = RE_TITLE.split(uri).collect();
+    match split.len() {
+        1 => (split[0], None),
+        2 => (split[0], Some(split[1])),
+        _ => (&uri[..], None),
     }
-    // TODO: assure the file exists
+}
+
+fn handle_query(uri: &str) -> (&str, Option<&str>) {
+    // split uri in (path, query)
+    let mut split = uri.splitn(2, '?');
+    (split.next().unwrap_or(""), split.next())
+}
+
+/// Fixes `file:` and `local:` paths, a path ending with a separator links to the `index_page` of
+/// that directory.
+fn fix_link_rest(uri: &str, input_dir: &Path, output_dir: &Path, index_page: &str) -> String {
     let (url_raw, title) = handle_title(&uri);
+    // a `#` starts the fragment, unless it is part of an existing file name
+    let target = url_raw
+        .strip_prefix("file:")
+        .or_else(|| url_raw.strip_prefix("local:"))
+        .unwrap_or(url_raw);
+    let (url_raw, fragment) = match handle_fragment(url_raw) {
+        (path, Some(fragment)) if !input_dir.join(target).exists() => (path, Some(fragment)),
+        _ => (url_raw, None),
+    };
+    // query and fragment are no part of the path, reattach them after the path is fixed
+    let (url_raw, query) = handle_query(url_raw);
     let url_path = {
         if url_raw.starts_with("file:") {
             // force absolute path
@@ -82,15 +145,26 @@ fn fix_link_rest(uri: &str, input_dir: &Path, output_dir: &Path) -> String {
             PathBuf::from(url_raw)
         }
     };
-    let url_complete = if !url_path.starts_with("http://") && !url_path.starts_with("https://") {
-        url_path.clean()
+    let is_prefixed = url_raw.starts_with("file:") || url_raw.starts_with("local:");
+    let url_path = if is_prefixed && url_raw.ends_with('/') {
+        url_path.join(index_page)
     } else {
         url_path
-    }
-    .handle_spaces()
-    .to_str()
-    .unwrap_or(url_raw) // something went wrong, take url
-    .to_owned();
+    };
+    let url_complete = url_path
+        .clean()
+        .percent_encode()
+        .to_str()
+        .unwrap_or(url_raw) // something went wrong, take url
+        .to_owned();
+    let url_complete = match query {
+        Some(query) => format!("{}?{}", url_complete, query),
+        None => url_complete,
+    };
+    let url_complete = match fragment {
+        Some(fragment) => format!("{}#{}", url_complete, handle_spaces(fragment)),
+        None => url_complete,
+    };
     match title {
         Some(title) => format!("{} \"{}", url_complete, title),
         None => url_complete,
@@ -100,39 +174,357 @@ fn fix_link_rest(uri: &str, input_dir: &Path, output_dir: &Path) -> String {
 /// Handles an input link split in `alt` and `uri` and returns a correct markdown link.
 ///
 /// This will handle relative and absolut paths to the new output_dir and corrects vimwiki
-/// references to point to html files
+/// references to point to the output files with the configured `output_extension`. `site:` paths,
+/// and root-absolute paths such as `/images/a.png` if `prefix_root_links` is set, are resolved
+/// against the root of the published site given by `base_url`.
 pub fn fix_link(
     alt: &str,
     uri: &str,
     input_file: &str,
     output_dir: &str,
     extension: &str,
+    program_options: &ProgramOptions,
+) -> String {
+    format!(
+        "[{}]({})",
+        alt,
+        fix_uri(uri, input_file, output_dir, extension, program_options)
+    )
+}
+
+/// Handles an input image split in `alt` and `uri` and returns a correct markdown image, see
+/// `fix_link`.
+///
+/// A size hint such as `=300x200` (either dimension is optional) at the end of `uri` results in an
+/// html `<img>` with the `width` and `height` attributes, as markdown cannot express them.
+///
+/// Images without `alt` text are inaccessible, a warning is logged for them.
+pub fn fix_image(
+    alt: &str,
+    uri: &str,
+    input_file: &str,
+    output_dir: &str,
+    extension: &str,
+    program_options: &ProgramOptions,
+) -> String {
+    if alt.trim().is_empty() {
+        warn!(
+            "Image `{}` in {} has no alt text",
+            handle_title(uri).0,
+            input_file
+        );
+    }
+    let size = RE_IMAGE_SIZE
+        .captures(uri)
+        .filter(|caps| !caps["width"].is_empty() || !caps["height"].is_empty());
+    let size = match size {
+        Some(size) => size,
+        None => {
+            return format!(
+                "!{}",
+                fix_link(alt, uri, input_file, output_dir, extension, program_options)
+            )
+        }
+    };
+    let uri = &uri[..size.get(0).unwrap().start()];
+    let fixed = fix_uri(uri, input_file, output_dir, extension, program_options);
+    let (src, title) = handle_title(&fixed);
+    let mut attributes = format!(
+        "src=\"{}\" alt=\"{}\"",
+        crate::escape_html(src),
+        crate::escape_html(alt)
+    );
+    if let Some(title) = title {
+        let title = title.strip_suffix('"').unwrap_or(title);
+        attributes.push_str(&format!(" title=\"{}\"", crate::escape_html(title)));
+    }
+    for dimension in ["width", "height"] {
+        if !size[dimension].is_empty() {
+            attributes.push_str(&format!(" {}=\"{}\"", dimension, &size[dimension]));
+        }
+    }
+    format!("<img {} />", attributes)
+}
+
+/// Handles a link reference definition split in `label` and `uri` and returns a correct markdown
+/// link reference definition, see `fix_link`.
+pub fn fix_link_definition(
+    label: &str,
+    uri: &str,
+    input_file: &str,
+    output_dir: &str,
+    extension: &str,
+    program_options: &ProgramOptions,
 ) -> String {
-    fn is_vimwiki_link(input_dir: &Path, uri: &str, ext: &str) -> bool {
+    format!(
+        "[{}]: {}",
+        label,
+        fix_uri(uri, input_file, output_dir, extension, program_options)
+    )
+}
+
+/// Converts the page name of a wikilink into a filename, e.g. `My Page` into `my-page`.
+fn filename_slug(page: &str) -> String {
+    page.trim()
+        .to_lowercase()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Rewrites vimwiki's `[[Target]]` and `[[Target|Label]]` links into markdown links to the
+/// slugified target, which are fixed afterwards like any other link. Code blocks and code spans
+/// are left as they are, e.g. the `[[ ... ]]` tests of shell scripts.
+pub fn convert_wikilinks(text: &str) -> String {
+    if !text.contains("[[") {
+        return text.to_owned();
+    }
+    split_code(text)
+        .into_iter()
+        .map(|(segment, is_code)| {
+            if is_code {
+                return Cow::Borrowed(segment);
+            }
+            RE_WIKILINK.replace_all(segment, |caps: &Captures| {
+                let target = &caps["target"];
+                let label = caps.name("label").map_or(target, |label| label.as_str());
+                let uri = match handle_fragment(target) {
+                    (page, Some(fragment)) => format!("{}#{}", filename_slug(page), fragment),
+                    (page, None) => filename_slug(page),
+                };
+                format!("[{}]({})", label.trim(), uri)
+            })
+        })
+        .collect()
+}
+
+/// Schemes of external links, which are passed through
+const EXTERNAL_SCHEMES: [&str; 4] = ["http://", "https://", "mailto:", "ftp://"];
+
+fn is_external_link(uri: &str) -> bool {
+    EXTERNAL_SCHEMES
+        .iter()
+        .any(|scheme| uri.to_lowercase().starts_with(scheme))
+}
+
+fn fix_link_external(uri: &str) -> String {
+    // only encode spaces, the url must not be treated as path
+    let (url_raw, title) = handle_title(uri);
+    let url_complete = handle_spaces(url_raw);
+    match title {
+        Some(title) => format!("{} \"{}", url_complete, title),
+        None => url_complete,
+    }
+}
+
+/// Resolves a `site:` path against the root of the published site, `base_url` or else `/`.
+fn fix_link_site(uri: &str, base_url: &str) -> String {
+    let (url_raw, title) = handle_title(uri);
+    let (url_raw, fragment) = handle_fragment(url_raw);
+    let (url_raw, query) = handle_query(url_raw);
+    let url_path = Path::new("/").join(url_raw).clean().percent_encode();
+    let url_complete = format!(
+        "{}{}",
+        base_url.trim_end_matches('/'),
+        url_path.to_str().unwrap_or(url_raw)
+    );
+    let url_complete = match query {
+        Some(query) => format!("{}?{}", url_complete, query),
+        None => url_complete,
+    };
+    let url_complete = match fragment {
+        Some(fragment) => format!("{}#{}", url_complete, handle_spaces(fragment)),
+        None => url_complete,
+    };
+    match title {
+        Some(title) => format!("{} \"{}", url_complete, title),
+        None => url_complete,
+    }
+}
+
+/// Returns the extensions of vimwiki pages, the wiki `extension` followed by the `wiki_extensions`.
+fn page_extensions<'a>(extension: &'a str, program_options: &'a ProgramOptions) -> Vec<&'a str> {
+    std::iter::once(extension)
+        .chain(program_options.wiki_extensions.iter().map(String::as_str))
+        .collect()
+}
+
+/// Returns the path without extension of the page `url_raw` links to, a path ending with a
+/// separator links to the `index_file` of that directory.
+fn vimwiki_page(input_dir: &Path, url_raw: &str, index_file: &str) -> PathBuf {
+    if url_raw.ends_with('/') {
+        input_dir.join(url_raw).join(index_file)
+    } else {
+        input_dir.join(url_raw)
+    }
+}
+
+/// Returns whether the `page` exists with any of the extensions `exts`.
+fn page_exists(page: &Path, exts: &[&str]) -> bool {
+    exts.iter().any(|ext| page.with_extension(ext).is_file())
+}
+
+fn fix_uri(
+    uri: &str,
+    input_file: &str,
+    output_dir: &str,
+    extension: &str,
+    program_options: &ProgramOptions,
+) -> String {
+    // a fragment of the same page is no path either
+    if is_external_link(uri) || uri.starts_with('#') {
+        return fix_link_external(uri);
+    }
+    if let Some(uri) = uri.strip_prefix("site:") {
+        return fix_link_site(uri, &program_options.base_url);
+    }
+    if program_options.prefix_root_links && uri.starts_with('/') && !uri.starts_with("//") {
+        return fix_link_site(uri, &program_options.base_url);
+    }
+
+    fn is_vimwiki_link(input_dir: &Path, uri: &str, exts: &[&str], index_file: &str) -> bool {
         // handle fragment
         let (url_raw, _) = handle_fragment(&uri);
-        input_dir
-            .join(Path::new(url_raw))
-            .with_extension(ext)
-            .is_file()
+        page_exists(&vimwiki_page(input_dir, url_raw, index_file), exts)
     }
     let uri: String = uri.to_owned();
 
     // necessary parameter
     let input_dir = Path::new(input_file).parent().unwrap();
     let output_dir = Path::new(output_dir);
+    let extensions = page_extensions(extension, program_options);
 
-    let uri: String = if is_vimwiki_link(input_dir, &uri, extension) {
-        fix_link_vimwiki(&uri)
+    if is_vimwiki_link(input_dir, &uri, &extensions, &program_options.index_file) {
+        fix_link_vimwiki(
+            &uri,
+            &program_options.output_extension,
+            &program_options.index_file,
+        )
     } else {
-        fix_link_rest(&uri, input_dir, output_dir)
+        let index_page = format!(
+            "{}.{}",
+            program_options.index_file, program_options.output_extension
+        );
+        fix_link_rest(&uri, input_dir, output_dir, &index_page)
+    }
+}
+
+/// Kind of a link target, see `extract_links`.
+#[derive(Debug, PartialEq)]
+pub enum LinkKind {
+    /// Another vimwiki page, i.e. any other relative or absolute path
+    Vimwiki,
+    /// A path relative to the output, `local:...`
+    Local,
+    /// An absolute path, `file:...`
+    File,
+    /// A path relative to the root of the published site, `site:...`
+    Site,
+    /// A url with a scheme such as `https://` or `mailto:`
+    External,
+    /// An anchor on the same page, `#...`
+    Fragment,
+}
+
+/// An outbound link of a page.
+#[derive(Debug, PartialEq)]
+pub struct Link {
+    /// The text of the link or the alt text of an image, the label of a reference definition
+    pub alt: String,
+    /// The target as written in the page, without a title
+    pub uri: String,
+    pub kind: LinkKind,
+}
+
+fn link_kind(uri: &str) -> LinkKind {
+    if is_external_link(uri) {
+        LinkKind::External
+    } else if uri.starts_with('#') {
+        LinkKind::Fragment
+    } else if uri.starts_with("local:") {
+        LinkKind::Local
+    } else if uri.starts_with("file:") {
+        LinkKind::File
+    } else if uri.starts_with("site:") {
+        LinkKind::Site
+    } else {
+        LinkKind::Vimwiki
+    }
+}
+
+/// Returns all links, images and link reference definitions of `markdown` in order of appearance.
+pub fn extract_links(markdown: &str) -> Vec<Link> {
+    let mut links: Vec<(usize, Link)> = RE_LINK
+        .captures_iter(markdown)
+        .map(|caps| (caps.get(0).unwrap().start(), caps["title"].to_owned(), caps))
+        .chain(
+            RE_LINK_DEF
+                .captures_iter(markdown)
+                .map(|caps| (caps.get(0).unwrap().start(), caps["label"].to_owned(), caps)),
+        )
+        .map(|(start, alt, caps)| {
+            let (uri, _) = handle_title(&caps["uri"]);
+            let link = Link {
+                alt,
+                uri: uri.to_owned(),
+                kind: link_kind(uri),
+            };
+            (start, link)
+        })
+        .collect();
+    links.sort_by_key(|(start, _)| *start);
+    links.into_iter().map(|(_, link)| link).collect()
+}
+
+/// Returns the targets of all images of `markdown` without alt text.
+pub fn images_without_alt(markdown: &str) -> Vec<String> {
+    RE_LINK
+        .captures_iter(markdown)
+        .filter(|caps| caps.name("image").is_some() && caps["title"].trim().is_empty())
+        .map(|caps| handle_title(&caps["uri"]).0.to_owned())
+        .collect()
+}
+
+/// Checks whether the target of a link exists and returns the missing path otherwise.
+///
+/// Only `file:` and `local:` targets and links to vimwiki pages (relative paths without extension)
+/// are checked, as all other links cannot be resolved reliably. Vimwiki pages are resolved like
+/// `fix_link` does, a page may have any of the wiki extensions.
+pub fn find_broken_link(
+    uri: &str,
+    input_file: &str,
+    extension: &str,
+    program_options: &ProgramOptions,
+) -> Option<String> {
+    let input_dir = Path::new(input_file).parent()?;
+    let (url_raw, _) = handle_title(uri);
+    let path = if let Some(path) = url_raw
+        .strip_prefix("file:")
+        .or_else(|| url_raw.strip_prefix("local:"))
+    {
+        input_dir.join(path)
+    } else {
+        let (url_raw, _) = handle_fragment(url_raw);
+        if url_raw.is_empty() || url_raw.contains(':') || Path::new(url_raw).extension().is_some() {
+            return None;
+        }
+        let page = vimwiki_page(input_dir, url_raw, &program_options.index_file);
+        if page_exists(&page, &page_extensions(extension, program_options)) {
+            return None;
+        }
+        page.with_extension(extension)
     };
-    format!("[{}]({})", alt, uri)
+    if path.exists() {
+        None
+    } else {
+        Some(path.clean().to_str().unwrap_or(url_raw).to_owned())
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_log::{init_logger, warnings_containing};
 
     fn to_fix_link(link: &str) -> String {
         let input_file = "/abs/path/to/vimwiki/bar/mdfile.wiki";
@@ -145,7 +537,14 @@ mod tests {
             Some(c) => (c["title"].to_string(), c["uri"].to_string()),
             None => ("".to_string(), "".to_string()),
         };
-        fix_link(&alt, &uri, input_file, output_dir, extension)
+        fix_link(
+            &alt,
+            &uri,
+            input_file,
+            output_dir,
+            extension,
+            &ProgramOptions::default(),
+        )
     }
     fn to_fix_link_vimwiki(link: &str) -> String {
         let re = Regex::new(r"\[(?P<title>.*)\]\((?P<uri>(.)*)\)").unwrap();
@@ -155,7 +554,7 @@ mod tests {
             Some(c) => (c["title"].to_string(), c["uri"].to_string()),
             None => ("".to_string(), "".to_string()),
         };
-        let uri = fix_link_vimwiki(&uri);
+        let uri = fix_link_vimwiki(&uri, "html", "index");
         format!("[{}]({})", alt, uri)
     }
 
@@ -189,6 +588,59 @@ mod tests {
         );
     }
 
+    #[test]
+    fn fix_link_vimwiki_directory() {
+        let link = "[diary](diary/)";
+        assert_eq!("[diary](diary/index.html)", to_fix_link_vimwiki(link));
+        let link = "[notes](../notes/#today)";
+        assert_eq!(
+            "[notes](../notes/index.html#today)",
+            to_fix_link_vimwiki(link)
+        );
+    }
+
+    #[test]
+    fn fix_link_vimwiki_fragment_heading_id() {
+        let html = crate::headings::add_anchors("<h2>What's new in v2.0?</h2>");
+        let link = fix_link_vimwiki("changelog#What's new in v2.0?", "html", "index");
+        let fragment = link.split('#').nth(1).unwrap();
+        assert!(html.contains(&format!("<h2 id=\"{}\">", fragment)));
+    }
+
+    #[test]
+    fn fragment_multiple_hashes() {
+        assert_eq!(("page", Some("sec#tion")), handle_fragment("page#sec#tion"));
+        assert_eq!(("page", None), handle_fragment("page"));
+    }
+
+    #[test]
+    fn fix_link_vimwiki_multiple_hashes() {
+        let link = "[Link Title](another_file#sec#tion)";
+        assert_eq!(
+            "[Link Title](another_file.html#section)",
+            to_fix_link_vimwiki(link)
+        );
+    }
+
+    #[test]
+    fn fix_link_vimwiki_fragment_slug() {
+        let link = "[Link Title](another_file#Getting Started)";
+        assert_eq!(
+            "[Link Title](another_file.html#getting-started)",
+            to_fix_link_vimwiki(link)
+        );
+        let link = "[Link Title](another_file#Introduction)";
+        assert_eq!(
+            "[Link Title](another_file.html#introduction)",
+            to_fix_link_vimwiki(link)
+        );
+        let link = "[Link Title](another_file#getting-started)";
+        assert_eq!(
+            "[Link Title](another_file.html#getting-started)",
+            to_fix_link_vimwiki(link)
+        );
+    }
+
     #[test]
     fn fix_link_relative() {
         // leave it unchanged as we force to use file: or local:
@@ -217,6 +669,12 @@ mod tests {
         assert_eq!("[alt](../../foo.png \"Title\")", to_fix_link(link));
     }
 
+    #[test]
+    fn fix_link_relative_local_query() {
+        let link = "[alt](local:../data.csv?v=2&raw=true)";
+        assert_eq!("[alt](../../data.csv?v=2&raw=true)", to_fix_link(link));
+    }
+
     #[test]
     fn fix_link_force_relative() {
         let link = "[alt](local:/abs/path/to/vimwiki/images/foo.png)";
@@ -270,6 +728,142 @@ mod tests {
         );
     }
 
+    #[test]
+    fn fix_link_reserved_chars() {
+        let link = "[alt](file:../images/100% <done>.png)";
+        assert_eq!(
+            "[alt](/abs/path/to/vimwiki/images/100%25%20%3Cdone%3E.png)",
+            to_fix_link(link)
+        );
+    }
+
+    #[test]
+    fn fix_link_already_encoded() {
+        let link = "[alt](file:../images/foo%20bar.png)";
+        assert_eq!(
+            "[alt](/abs/path/to/vimwiki/images/foo%20bar.png)",
+            to_fix_link(link)
+        );
+    }
+
+    #[test]
+    fn fix_link_local_fragment() {
+        let link = "[alt](local:../other.html#section 1)";
+        assert_eq!("[alt](../../other.html#section%201)", to_fix_link(link));
+    }
+
+    #[test]
+    fn fix_link_same_page_fragment() {
+        assert_eq!("[top](#top)", to_fix_link("[top](#top)"));
+        assert_eq!(
+            "[alt](#my%20section \"Title\")",
+            to_fix_link("[alt](#my section \"Title\")")
+        );
+    }
+
+    #[test]
+    fn fix_link_literal_hash() {
+        let dir = std::env::temp_dir().join("vimwiki-markdown-rs-links");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("issue#1.png"), "").unwrap();
+        let input_file = dir.join("page.wiki");
+        let fixed = fix_link(
+            "alt",
+            "local:issue#1.png",
+            input_file.to_str().unwrap(),
+            dir.to_str().unwrap(),
+            "wiki",
+            &ProgramOptions::default(),
+        );
+        assert_eq!("[alt](issue%231.png)", fixed);
+    }
+
+    #[test]
+    fn fix_link_wiki_extensions() {
+        let dir = std::env::temp_dir().join("vimwiki-markdown-rs-links/wiki_extensions");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("notes.md"), "").unwrap();
+        let input_file = dir.join("page.wiki");
+        let fix = |program_options: &ProgramOptions| {
+            fix_link(
+                "Notes",
+                "notes#todo",
+                input_file.to_str().unwrap(),
+                dir.to_str().unwrap(),
+                "wiki",
+                program_options,
+            )
+        };
+        assert_eq!("[Notes](notes#todo)", fix(&ProgramOptions::default()));
+        let program_options = ProgramOptions {
+            wiki_extensions: vec!["md".to_string()],
+            ..ProgramOptions::default()
+        };
+        assert_eq!("[Notes](notes.html#todo)", fix(&program_options));
+    }
+
+    #[test]
+    fn fix_link_site() {
+        let link = "[logo](site:images/logo.png)";
+        assert_eq!("[logo](/images/logo.png)", to_fix_link(link));
+        let fixed = fix_link(
+            "logo",
+            "site:/images/my logo.png \"Logo\"",
+            "/abs/path/to/vimwiki/bar/mdfile.wiki",
+            "/abs/path/to/vimwiki/site_html/bar/",
+            "wiki",
+            &ProgramOptions {
+                base_url: "https://example.com/wiki/".to_string(),
+                ..ProgramOptions::default()
+            },
+        );
+        assert_eq!(
+            "[logo](https://example.com/wiki/images/my%20logo.png \"Logo\")",
+            fixed
+        );
+    }
+
+    #[test]
+    fn fix_link_root_absolute() {
+        let fix = |uri, prefix_root_links| {
+            fix_image(
+                "a",
+                uri,
+                "/abs/path/to/vimwiki/bar/mdfile.wiki",
+                "/abs/path/to/vimwiki/site_html/bar/",
+                "wiki",
+                &ProgramOptions {
+                    base_url: "https://site/wiki/".to_string(),
+                    prefix_root_links,
+                    ..ProgramOptions::default()
+                },
+            )
+        };
+        assert_eq!("![a](/images/a.png)", fix("/images/a.png", false));
+        assert_eq!(
+            "![a](https://site/wiki/images/a.png)",
+            fix("/images/a.png", true)
+        );
+        assert_eq!(
+            "![a](https://site/wiki/images/a.png \"A\")",
+            fix("/images/a.png \"A\"", true)
+        );
+        // relative and external links are kept
+        assert_eq!("![a](images/a.png)", fix("images/a.png", true));
+        assert_eq!(
+            "![a](https://other/a.png)",
+            fix("https://other/a.png", true)
+        );
+    }
+
+    #[test]
+    fn fix_link_local_directory() {
+        let link = "[docs](local:../manual/)";
+        assert_eq!("[docs](../../manual/index.html)", to_fix_link(link));
+        let link = "[docs](local:../manual/#install)";
+        assert_eq!("[docs](../../manual/index.html#install)", to_fix_link(link));
+    }
+
     #[test]
     fn link_real() {
         let link = "[Inkscape](https://www.inkscape.org/)";
@@ -285,6 +879,193 @@ mod tests {
         );
     }
 
+    #[test]
+    fn fix_image_local() {
+        let input_file = "/abs/path/to/vimwiki/bar/mdfile.wiki";
+        let output_dir = "/abs/path/to/vimwiki/site_html/bar/";
+        assert_eq!(
+            "![diagram](../../img.png)",
+            fix_image(
+                "diagram",
+                "local:../img.png",
+                input_file,
+                output_dir,
+                "wiki",
+                &ProgramOptions::default()
+            )
+        );
+    }
+
+    #[test]
+    fn fix_image_alt() {
+        init_logger();
+        let input_file = "/abs/path/to/vimwiki/bar/mdfile.wiki";
+        let output_dir = "/abs/path/to/vimwiki/site_html/bar/";
+        let fix = |alt, uri| {
+            fix_image(
+                alt,
+                uri,
+                input_file,
+                output_dir,
+                "wiki",
+                &ProgramOptions::default(),
+            )
+        };
+        assert_eq!("![](../../no-alt.png)", fix("", "local:../no-alt.png"));
+        assert_eq!(
+            1,
+            warnings_containing(&format!(
+                "Image `local:../no-alt.png` in {} has no alt text",
+                input_file
+            ))
+        );
+        assert_eq!(
+            "![A diagram](../../with-alt.png)",
+            fix("A diagram", "local:../with-alt.png")
+        );
+        assert_eq!(0, warnings_containing("with-alt.png"));
+
+        assert_eq!(
+            vec!["a.png", "c.png"],
+            images_without_alt("![](a.png) ![b](b.png) [](link) ![ ](c.png \"C\")")
+        );
+    }
+
+    #[test]
+    fn fix_image_size() {
+        let input_file = "/abs/path/to/vimwiki/bar/mdfile.wiki";
+        let output_dir = "/abs/path/to/vimwiki/site_html/bar/";
+        let fix = |uri| {
+            fix_image(
+                "diagram",
+                uri,
+                input_file,
+                output_dir,
+                "wiki",
+                &ProgramOptions::default(),
+            )
+        };
+        assert_eq!(
+            "<img src=\"../../img.png\" alt=\"diagram\" width=\"300\" />",
+            fix("local:../img.png =300x")
+        );
+        assert_eq!(
+            "<img src=\"../../img.png\" alt=\"diagram\" height=\"200\" />",
+            fix("local:../img.png =x200")
+        );
+        assert_eq!(
+            "<img src=\"../../img.png\" alt=\"diagram\" title=\"A diagram\" width=\"300\" \
+             height=\"200\" />",
+            fix("local:../img.png \"A diagram\" =300x200")
+        );
+    }
+
+    #[test]
+    fn fix_link_definition_local() {
+        let input_file = "/abs/path/to/vimwiki/bar/mdfile.wiki";
+        let output_dir = "/abs/path/to/vimwiki/site_html/bar/";
+        assert_eq!(
+            "[img]: ../../img.png \"Title\"",
+            fix_link_definition(
+                "img",
+                "local:../img.png \"Title\"",
+                input_file,
+                output_dir,
+                "wiki",
+                &ProgramOptions::default()
+            )
+        );
+    }
+
+    #[test]
+    fn fix_link_definition_vimwiki() {
+        let input_file = concat!(env!("CARGO_MANIFEST_DIR"), "/src/lib.rs");
+        let output_dir = concat!(env!("CARGO_MANIFEST_DIR"), "/site_html/");
+        assert_eq!(
+            "[page]: links.html#tests",
+            fix_link_definition(
+                "page",
+                "links#tests",
+                input_file,
+                output_dir,
+                "rs",
+                &ProgramOptions::default()
+            )
+        );
+    }
+
+    #[test]
+    fn fix_link_output_extension() {
+        assert_eq!(
+            "another_file.htm#section",
+            fix_link_vimwiki("another_file#section", "htm", "index")
+        );
+        assert_eq!(
+            "../another_file.xhtml",
+            fix_link_vimwiki("../another_file.wiki", "xhtml", "index")
+        );
+    }
+
+    #[test]
+    fn broken_link_local() {
+        let input_file = concat!(env!("CARGO_MANIFEST_DIR"), "/src/lib.rs");
+        let po = ProgramOptions::default();
+        assert_eq!(
+            None,
+            find_broken_link("local:links.rs", input_file, "rs", &po)
+        );
+        assert!(find_broken_link("local:../images/missing.png", input_file, "rs", &po).is_some());
+    }
+
+    #[test]
+    fn broken_link_vimwiki() {
+        let input_file = concat!(env!("CARGO_MANIFEST_DIR"), "/src/lib.rs");
+        let po = ProgramOptions::default();
+        assert_eq!(None, find_broken_link("links#tests", input_file, "rs", &po));
+        assert!(find_broken_link("missing_page", input_file, "rs", &po).is_some());
+        assert_eq!(
+            None,
+            find_broken_link("https://example.com", input_file, "rs", &po)
+        );
+    }
+
+    #[test]
+    fn broken_link_directory_and_wiki_extensions() {
+        let dir = std::env::temp_dir().join("vimwiki-markdown-rs-links/broken_link");
+        std::fs::create_dir_all(dir.join("diary")).unwrap();
+        std::fs::write(dir.join("diary/index.wiki"), "# Diary\n").unwrap();
+        std::fs::write(dir.join("notes.md"), "# Notes\n").unwrap();
+        let input_file = dir.join("index.wiki");
+        let input_file = input_file.to_str().unwrap();
+        let po = ProgramOptions {
+            wiki_extensions: vec!["md".to_string()],
+            ..ProgramOptions::default()
+        };
+        assert_eq!(None, find_broken_link("diary/", input_file, "wiki", &po));
+        assert_eq!(None, find_broken_link("notes", input_file, "wiki", &po));
+        assert!(
+            find_broken_link("notes", input_file, "wiki", &ProgramOptions::default()).is_some()
+        );
+        assert!(find_broken_link("journal/", input_file, "wiki", &po)
+            .unwrap()
+            .ends_with("journal/index.wiki"));
+    }
+
+    #[test]
+    fn link_real_spaces() {
+        let link = "[site](https://example.com/a b?q=c d \"Title\")";
+        assert_eq!(
+            "[site](https://example.com/a%20b?q=c%20d \"Title\")",
+            to_fix_link(link)
+        );
+    }
+
+    #[test]
+    fn link_mailto() {
+        let link = "[mail](mailto:someone@example.com)";
+        assert_eq!("[mail](mailto:someone@example.com)", to_fix_link(link));
+    }
+
     #[test]
     fn relative_paths() {
         let p1 = Path::new("/abs/path/to/Document/foo.xyz");
@@ -294,4 +1075,57 @@ mod tests {
             pathdiff::diff_paths(&p1, &p2).unwrap()
         );
     }
+
+    #[test]
+    fn wikilink_plain() {
+        assert_eq!(
+            "See [Target](target).",
+            convert_wikilinks("See [[Target]].")
+        );
+        assert_eq!(
+            "[My Page#Some Heading](my-page#Some Heading)",
+            convert_wikilinks("[[My Page#Some Heading]]")
+        );
+    }
+
+    #[test]
+    fn wikilink_label() {
+        assert_eq!(
+            "[the label](diary/my-page)",
+            convert_wikilinks("[[diary/My Page|the label]]")
+        );
+    }
+
+    #[test]
+    fn wikilink_in_code() {
+        let markdown = "```bash\nif [[ -f x ]]; then\n```\n\nRun `[[ -d y ]]` or see [[Page]]\n";
+        assert_eq!(
+            "```bash\nif [[ -f x ]]; then\n```\n\nRun `[[ -d y ]]` or see [Page](page)\n",
+            convert_wikilinks(markdown)
+        );
+    }
+
+    #[test]
+    fn extract_links_kinds() {
+        let markdown = "[Page](other_page#intro) and back to [top](#top)\n\
+                        ![img](local:../img.png \"Title\")\n\
+                        [pdf]: file:/docs/paper.pdf\n\
+                        [web](https://example.com)\n";
+        let kinds: Vec<(&str, &str, LinkKind)> = vec![
+            ("Page", "other_page#intro", LinkKind::Vimwiki),
+            ("top", "#top", LinkKind::Fragment),
+            ("img", "local:../img.png", LinkKind::Local),
+            ("pdf", "file:/docs/paper.pdf", LinkKind::File),
+            ("web", "https://example.com", LinkKind::External),
+        ];
+        let expected: Vec<Link> = kinds
+            .into_iter()
+            .map(|(alt, uri, kind)| Link {
+                alt: alt.to_owned(),
+                uri: uri.to_owned(),
+                kind,
+            })
+            .collect();
+        assert_eq!(expected, extract_links(markdown));
+    }
 }
@@ -86,6 +86,7 @@ impl From<VimWikiCmdlineArgs> for VimWikiOptions {
             &cmdline_args.root_path,
             &cmdline_args.output_dir,
             &cmdline_args.input_file,
+            &cmdline_args.css_file,
         )
     }
 }
@@ -100,7 +101,8 @@ fn main() -> Result<()> {
 
     // get user specific configurations
     info!("Loading configuration file...");
-    let program_options = vimwiki_markdown_rs::ProgramOptions::new();
+    let program_options =
+        vimwiki_markdown_rs::ProgramOptions::load_for_input(&wiki_cmdline_args.input_file)?;
 
     // run method, send Error back to user (vimwiki plugin)
     info!("Generating html file...");
@@ -0,0 +1,48 @@
+use lazy_static::lazy_static;
+use regex::{Captures, Regex};
+
+use crate::replace_all_owned;
+
+/// Loads mermaid.js, which renders every `div.mermaid` of the page once it is loaded
+pub const MERMAID_SCRIPT: &str = "<script type=\"module\">\
+import mermaid from 'https://cdn.jsdelivr.net/npm/mermaid@10/dist/mermaid.esm.min.mjs'; \
+mermaid.initialize({ startOnLoad: true });</script>";
+
+lazy_static! {
+    static ref RE_MERMAID_BLOCK: Regex =
+        Regex::new(r#"<pre><code class="language-mermaid">(?P<code>(?s).*?)</code></pre>"#)
+            .unwrap();
+}
+
+/// Rewrites the `mermaid` code blocks of `html` into `<div class="mermaid">` elements holding the
+/// diagram source. The source stays escaped, mermaid.js reads the text of the element.
+pub fn diagrams(html: String) -> String {
+    replace_all_owned(&RE_MERMAID_BLOCK, html, |caps: &Captures| {
+        format!("<div class=\"mermaid\">{}</div>", &caps["code"])
+    })
+}
+
+/// Returns whether `html` contains a diagram, see `diagrams`.
+pub fn has_diagrams(html: &str) -> bool {
+    html.contains("<div class=\"mermaid\">")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mermaid_block() {
+        let html =
+            "<pre><code class=\"language-mermaid\">graph TD\n  A[&quot;Start&quot;] --&gt; B\n\
+                    </code></pre>\n<pre><code class=\"language-rust\">a &lt; b</code></pre>";
+        let html = diagrams(html.to_string());
+        assert_eq!(
+            "<div class=\"mermaid\">graph TD\n  A[&quot;Start&quot;] --&gt; B\n</div>\n\
+             <pre><code class=\"language-rust\">a &lt; b</code></pre>",
+            html
+        );
+        assert!(has_diagrams(&html));
+        assert!(!has_diagrams("<p>No diagram</p>"));
+    }
+}
@@ -0,0 +1,105 @@
+use anyhow::Result;
+use serde_yaml::Value;
+use std::collections::BTreeMap;
+
+use crate::frontmatter::FrontMatter;
+use crate::headings::{self, Heading};
+use crate::links::{self, Link};
+use crate::{commands, reading, render_body, ProgramOptions};
+
+/// Metadata of a page, see `convert_with_meta`.
+#[derive(Debug, PartialEq)]
+pub struct PageMeta {
+    /// The text of the first `h1`, empty if there is none
+    pub title: String,
+    pub headings: Vec<Heading>,
+    /// All links, images and link reference definitions as written in the page
+    pub links: Vec<Link>,
+    pub word_count: usize,
+    /// All keys of the front matter with their values
+    pub front_matter: BTreeMap<String, Value>,
+}
+
+/// Converts `markdown` to the body html like `markdown_to_html` and collects the metadata of the
+/// page on the way. A front matter block is not part of the html but returned in the metadata.
+///
+/// # Errors
+///
+/// Will return `Err` if the front matter is invalid or a used variable is not defined.
+pub fn convert_with_meta(
+    markdown: &str,
+    program_options: &ProgramOptions,
+) -> Result<(String, PageMeta)> {
+    let (front_matter, text) = FrontMatter::split_fields(markdown)?;
+    let (text, variables) = commands::parse_variables(text)?;
+    let text = links::convert_wikilinks(&text);
+    let links = links::extract_links(&text);
+    let html = render_body(text, &variables, program_options);
+    let meta = PageMeta {
+        title: headings::first_h1(&html).unwrap_or_default(),
+        headings: headings::headings(&html),
+        links,
+        word_count: reading::word_count(&html),
+        front_matter,
+    };
+    Ok((html, meta))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::links::LinkKind;
+
+    #[test]
+    fn page_meta() {
+        let markdown = "---\nauthor: Jane\ntags: [rust, wiki]\n---\n# Notes\n\n\
+                        Read [[Other Page]] and [docs](https://docs.rs)\n\n\
+                        ## Open Tasks\n\n![logo](local:logo.png)\n";
+        let (html, meta) = convert_with_meta(markdown, &ProgramOptions::default()).unwrap();
+        assert!(html.starts_with("<h1 id=\"notes\">Notes</h1>"));
+        assert!(!html.contains("author"));
+
+        assert_eq!("Notes", meta.title);
+        assert_eq!(
+            vec![
+                Heading {
+                    level: 1,
+                    id: "notes".to_string(),
+                    text: "Notes".to_string()
+                },
+                Heading {
+                    level: 2,
+                    id: "open-tasks".to_string(),
+                    text: "Open Tasks".to_string()
+                },
+            ],
+            meta.headings
+        );
+        assert_eq!(
+            vec![
+                Link {
+                    alt: "Other Page".to_string(),
+                    uri: "other-page".to_string(),
+                    kind: LinkKind::Vimwiki
+                },
+                Link {
+                    alt: "docs".to_string(),
+                    uri: "https://docs.rs".to_string(),
+                    kind: LinkKind::External
+                },
+                Link {
+                    alt: "logo".to_string(),
+                    uri: "local:logo.png".to_string(),
+                    kind: LinkKind::Local
+                },
+            ],
+            meta.links
+        );
+        assert_eq!(8, meta.word_count);
+        assert_eq!(Value::from("Jane"), meta.front_matter["author"]);
+        assert_eq!(
+            Value::Sequence(vec![Value::from("rust"), Value::from("wiki")]),
+            meta.front_matter["tags"]
+        );
+    }
+}
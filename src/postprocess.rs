@@ -0,0 +1,112 @@
+use anyhow::{anyhow, Result};
+use log::warn;
+use std::fs;
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+static TEMP_FILES: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns a command running `command_line` in the shell of the platform.
+fn shell(command_line: &str) -> Command {
+    if cfg!(windows) {
+        let mut command = Command::new("cmd");
+        command.args(["/C", command_line]);
+        command
+    } else {
+        let mut command = Command::new("sh");
+        command.args(["-c", command_line]);
+        command
+    }
+}
+
+/// Runs `command_line` on `html` and returns its output.
+///
+/// A `{}` in `command_line` is replaced by the path of a temporary file containing `html`,
+/// otherwise `html` is passed on stdin.
+fn run(command_line: &str, html: &str) -> Result<Output> {
+    if command_line.contains("{}") {
+        let file = std::env::temp_dir().join(format!(
+            "vimwiki-markdown-rs-{}-{}.html",
+            std::process::id(),
+            TEMP_FILES.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::write(&file, html)?;
+        let output = shell(&command_line.replace("{}", file.to_str().unwrap_or("")))
+            .stdin(Stdio::null())
+            .output();
+        fs::remove_file(&file).unwrap_or(());
+        return Ok(output?);
+    }
+
+    let mut child = shell(command_line)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let mut stdin = child
+        .stdin
+        .take()
+        .ok_or_else(|| anyhow!("Could not open stdin"))?;
+    // write on a separate thread, a full stdout pipe would block the command otherwise
+    let input = html.to_string();
+    let writer = thread::spawn(move || stdin.write_all(input.as_bytes()));
+    let output = child.wait_with_output()?;
+    writer
+        .join()
+        .map_err(|_| anyhow!("Could not write to stdin"))??;
+    Ok(output)
+}
+
+/// Pipes `html` through the external `command_line` and returns its stdout.
+///
+/// If the command cannot be run or exits with an error, its stderr is logged and `html` is
+/// returned unchanged.
+pub fn post_process(html: &str, command_line: &str) -> String {
+    match run(command_line, html) {
+        Ok(output) if output.status.success() => match String::from_utf8(output.stdout) {
+            Ok(processed) => processed,
+            Err(_) => {
+                warn!("Output of `{}` is no valid UTF-8", command_line);
+                html.to_string()
+            }
+        },
+        Ok(output) => {
+            warn!(
+                "Post-processing with `{}` failed ({}): {}",
+                command_line,
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+            html.to_string()
+        }
+        Err(err) => {
+            warn!("Could not run `{}`: {}", command_line, err);
+            html.to_string()
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn post_process_stdin() {
+        assert_eq!("<p>Text</p>", post_process("<p>Text</p>", "cat"));
+        assert_eq!("<P>TEXT</P>", post_process("<p>Text</p>", "tr a-z A-Z"));
+    }
+
+    #[test]
+    fn post_process_file() {
+        assert_eq!("<p>Text</p>", post_process("<p>Text</p>", "cat {}"));
+    }
+
+    #[test]
+    fn post_process_failed() {
+        let html = "<p>Text</p>";
+        assert_eq!(html, post_process(html, "echo broken >&2; exit 1"));
+        assert_eq!(html, post_process(html, "no-such-command-for-tests"));
+    }
+}
@@ -0,0 +1,63 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::headings::ANCHOR_LINK_CLASS;
+
+lazy_static! {
+    static ref RE_TAG: Regex = Regex::new(r"<[^>]*>").unwrap();
+    static ref RE_ANCHOR_LINK: Regex = Regex::new(&format!(
+        r#"<a class="{}"[^>]*>[^<]*</a>"#,
+        ANCHOR_LINK_CLASS
+    ))
+    .unwrap();
+}
+
+/// Returns the text of `html` without tags and the symbols of heading anchor links, its words
+/// separated by single spaces.
+pub fn plain_text(html: &str) -> String {
+    text(html).split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Returns the number of words of the text of `html`, see `plain_text`.
+pub fn word_count(html: &str) -> usize {
+    text(html).split_whitespace().count()
+}
+
+/// Replaces the tags and heading anchor links of `html` by spaces.
+fn text(html: &str) -> String {
+    let html = RE_ANCHOR_LINK.replace_all(html, " ");
+    RE_TAG.replace_all(&html, " ").into_owned()
+}
+
+/// Returns the minutes needed to read `words` at `words_per_minute`, rounded up.
+pub fn reading_time(words: usize, words_per_minute: usize) -> usize {
+    words.div_ceil(words_per_minute.max(1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn count_words() {
+        assert_eq!(
+            4,
+            word_count("<h1>Title</h1>\n<p>Some <em>more</em> text</p>")
+        );
+        assert_eq!(0, word_count("<hr />"));
+    }
+
+    #[test]
+    fn anchor_symbols_ignored() {
+        let html = crate::headings::link_anchors("<h1 id=\"title\">Title</h1><p>Text</p>", "¶");
+        assert_eq!(2, word_count(&html));
+        assert_eq!("Title Text", plain_text(&html));
+    }
+
+    #[test]
+    fn reading_time_rounds_up() {
+        assert_eq!(0, reading_time(0, 200));
+        assert_eq!(1, reading_time(200, 200));
+        assert_eq!(2, reading_time(201, 200));
+    }
+}
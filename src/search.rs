@@ -0,0 +1,57 @@
+use serde::Serialize;
+
+use crate::reading::plain_text;
+use crate::unescape_html;
+
+/// A page of the search index.
+#[derive(Debug, Serialize)]
+pub struct SearchEntry {
+    pub title: String,
+    pub url: String,
+    /// The plain text of the body
+    pub text: String,
+}
+
+impl SearchEntry {
+    /// Creates an entry of the page at `url`, its `text` is extracted from `body_html`.
+    pub fn new(title: &str, url: &str, body_html: &str) -> Self {
+        Self {
+            title: title.to_owned(),
+            url: url.to_owned(),
+            text: unescape_html(&plain_text(body_html)),
+        }
+    }
+}
+
+/// Serializes the search index of `pages` as a JSON array of `{title, url, text}` objects, which
+/// can be loaded by a client-side search.
+pub fn build_search_index(pages: &[SearchEntry]) -> String {
+    serde_json::to_string(pages).unwrap_or_else(|_| String::from("[]"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    #[test]
+    fn search_index_two_pages() {
+        let pages = [
+            SearchEntry::new(
+                "Index",
+                "index.html",
+                "<h1 id=\"index\">Index</h1>\n<p>See  the\n<em>notes</em></p>\n",
+            ),
+            SearchEntry::new("Notes", "sub/notes.html", "<p>Fish &amp; chips &lt;3</p>"),
+        ];
+        let index: Value = serde_json::from_str(&build_search_index(&pages)).unwrap();
+        assert_eq!(
+            json!([
+                {"title": "Index", "url": "index.html", "text": "Index See the notes"},
+                {"title": "Notes", "url": "sub/notes.html", "text": "Fish & chips <3"},
+            ]),
+            index
+        );
+        assert_eq!("[]", build_search_index(&[]));
+    }
+}
@@ -0,0 +1,59 @@
+use chrono::{DateTime, Utc};
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use crate::escape_html;
+
+/// Serializes a `sitemap.xml` listing every page of `entries`.
+///
+/// Each entry is the path of a page relative to the output root and the modification time of its
+/// source, which is used as `lastmod`. The page urls are prefixed with `base_url`.
+pub fn write_sitemap(entries: &[(PathBuf, SystemTime)], base_url: &str) -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n",
+    );
+    for (path, modified) in entries {
+        let path = path.to_str().unwrap_or("").replace('\\', "/");
+        let url = format!(
+            "{}/{}",
+            base_url.trim_end_matches('/'),
+            path.trim_start_matches('/').replace(' ', "%20")
+        );
+        let lastmod = DateTime::<Utc>::from(*modified).format("%Y-%m-%d");
+        xml.push_str(&format!(
+            "  <url>\n    <loc>{}</loc>\n    <lastmod>{}</lastmod>\n  </url>\n",
+            escape_html(&url),
+            lastmod
+        ));
+    }
+    xml.push_str("</urlset>\n");
+    xml
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn sitemap_two_entries() {
+        let entries = vec![
+            (PathBuf::from("index.html"), SystemTime::UNIX_EPOCH),
+            (
+                PathBuf::from("diary/my notes.html"),
+                SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+            ),
+        ];
+        assert_eq!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n  \
+             <url>\n    <loc>https://example.com/index.html</loc>\n    \
+             <lastmod>1970-01-01</lastmod>\n  </url>\n  \
+             <url>\n    <loc>https://example.com/diary/my%20notes.html</loc>\n    \
+             <lastmod>2023-11-14</lastmod>\n  </url>\n\
+             </urlset>\n",
+            write_sitemap(&entries, "https://example.com/")
+        );
+    }
+}
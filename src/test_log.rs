@@ -0,0 +1,40 @@
+//! Logger for tests, collecting all messages so that tests can check for warnings.
+
+use lazy_static::lazy_static;
+use log::{LevelFilter, Log, Metadata, Record};
+use std::sync::Mutex;
+
+lazy_static! {
+    static ref WARNINGS: Mutex<Vec<String>> = Mutex::new(vec![]);
+}
+
+struct TestLogger;
+
+impl Log for TestLogger {
+    fn enabled(&self, _: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        WARNINGS.lock().unwrap().push(record.args().to_string());
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: TestLogger = TestLogger;
+
+pub fn init_logger() {
+    log::set_logger(&LOGGER).unwrap_or(());
+    log::set_max_level(LevelFilter::Warn);
+}
+
+/// Returns the number of logged messages containing `pattern`.
+pub fn warnings_containing(pattern: &str) -> usize {
+    WARNINGS
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|warning| warning.contains(pattern))
+        .count()
+}
@@ -0,0 +1,107 @@
+use std::fs;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+#[test]
+fn stdin_to_stdout() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_vimwiki-md2html"))
+        .args(&["-o", "-", "-"])
+        .env("RUST_LOG", "error")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"# Piped\n\nHello *world*\n")
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+
+    let html = String::from_utf8(output.stdout).unwrap();
+    assert!(html.contains("<h1 id=\"piped\">Piped</h1>"));
+    assert!(html.contains("<p>Hello <em>world</em></p>"));
+}
+
+#[test]
+fn convert_directory() {
+    let dir = std::env::temp_dir().join("vimwiki-markdown-rs-cli-dir");
+    let input = dir.join("wiki");
+    let output = dir.join("site_html");
+    fs::remove_dir_all(&dir).unwrap_or(());
+    fs::create_dir_all(input.join("sub")).unwrap();
+    fs::write(input.join("index.wiki"), "# Index\n").unwrap();
+    fs::write(input.join("sub").join("page.wiki"), "# Page\n").unwrap();
+    fs::write(input.join("sub").join("notes.txt"), "not a page").unwrap();
+
+    let status = Command::new(env!("CARGO_BIN_EXE_vimwiki-md2html"))
+        .arg("-o")
+        .arg(&output)
+        .arg(&input)
+        .env("RUST_LOG", "error")
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let index = fs::read_to_string(output.join("index.html")).unwrap();
+    assert!(index.contains("href=\"./style.css\""));
+    let page = fs::read_to_string(output.join("sub").join("page.html")).unwrap();
+    assert!(page.contains("href=\"../style.css\""));
+    assert!(!output.join("sub").join("notes.html").exists());
+}
+
+#[test]
+fn check_broken_link() {
+    let dir = std::env::temp_dir().join("vimwiki-markdown-rs-cli-check");
+    fs::remove_dir_all(&dir).unwrap_or(());
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("index.wiki"), "# Index\n\nSee [notes](notes)\n").unwrap();
+    fs::write(dir.join("notes.wiki"), "Back to [index](index)\n").unwrap();
+
+    let check = || {
+        Command::new(env!("CARGO_BIN_EXE_vimwiki-md2html"))
+            .arg("--check")
+            .arg(&dir)
+            .env("RUST_LOG", "error")
+            .output()
+            .unwrap()
+    };
+    assert!(check().status.success());
+
+    fs::write(dir.join("notes.wiki"), "See [missing](missing)\n").unwrap();
+    let output = check();
+    assert!(!output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("notes.wiki: Broken link: `"));
+    assert!(stdout.contains("missing.wiki` does not exist"));
+    assert_eq!(1, stdout.lines().count());
+    assert!(!dir.join("index.html").exists() && !dir.join("notes.html").exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn post_process_stdout() {
+    let dir = std::env::temp_dir().join("vimwiki-markdown-rs-cli-post-process");
+    fs::remove_dir_all(&dir).unwrap_or(());
+    fs::create_dir_all(&dir).unwrap();
+    let config = dir.join("config.toml");
+    fs::write(&config, "post_process_cmd = \"tr a-z A-Z\"\n").unwrap();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_vimwiki-md2html"))
+        .arg("--config")
+        .arg(&config)
+        .args(&["-o", "-", "-"])
+        .env("RUST_LOG", "error")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(b"# Loud\n").unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+
+    let html = String::from_utf8(output.stdout).unwrap();
+    assert!(html.contains("<H1 ID=\"LOUD\">LOUD</H1>"));
+}